@@ -0,0 +1,857 @@
+//! Launch descriptions of the bins `rtpptdemux` links each payload type to.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use gst::prelude::*;
+use gst_video::prelude::*;
+
+use crate::{H264Format, Hwaccel, OutputFormat, WindowGeometry};
+
+/// What the branches do with the streams.
+#[derive(Debug, Clone)]
+pub(crate) struct BranchOptions {
+    /// Encode the first video keyframe to this JPEG file, then EOS.
+    pub(crate) snapshot: Option<PathBuf>,
+    /// Save a JPEG of the video this often, to this directory, with names starting with
+    /// this.
+    pub(crate) snapshot_interval: Option<(Duration, PathBuf, String)>,
+    /// Write H.264 and H.265 video to this file after parsing, as an elementary stream.
+    pub(crate) dump_es: Option<PathBuf>,
+    pub(crate) h264_format: H264Format,
+    /// Leave a video branch unlinked after parsing, for the recording muxer.
+    pub(crate) record: bool,
+    pub(crate) output_format: OutputFormat,
+    /// Record through `splitmuxsink`, into a file per segment.
+    pub(crate) segmented: bool,
+    /// The unlinked video branch is for `flvmux`, so of H.264.
+    pub(crate) rtmp: bool,
+    /// Don't decode or display video.
+    pub(crate) headless: bool,
+    /// Element decoding video, unless `hwaccel` picks one.
+    pub(crate) decoder: String,
+    pub(crate) hwaccel: Option<Hwaccel>,
+    /// Element displaying video.
+    pub(crate) video_sink: String,
+    /// Don't play audio, just decode it.
+    pub(crate) mute: bool,
+    /// Title of its window, if it takes one from the stream's tags.
+    pub(crate) window_title: Option<String>,
+    /// Size and position of its window, if it's a video overlay.
+    pub(crate) window_geometry: Option<WindowGeometry>,
+    /// Log the caps at every pad of the branch.
+    pub(crate) verbose_caps: bool,
+    /// Reorder and smooth packets in an `rtpjitterbuffer` with this latency.
+    pub(crate) jitter_latency: Option<Duration>,
+    /// Most the queue before each sink holds; the queue's default if not given.
+    pub(crate) queue_size: Option<Duration>,
+    /// Keep only the latest buffer before each sink, and don't sync the sinks.
+    pub(crate) low_latency: bool,
+    /// Keep the branch's flow errors from stopping appsrc, so it can be restarted.
+    pub(crate) recover_on_error: bool,
+    /// Launch descriptions replacing the built-in ones, by encoding-name.
+    pub(crate) overrides: HashMap<String, String>,
+}
+
+impl BranchOptions {
+    /// Template of the recording muxer's request pads for `media` streams.
+    pub(crate) fn mux_pad_template(&self, media: &str) -> String {
+        if self.rtmp {
+            return media.to_owned();
+        }
+
+        match (self.segmented, media) {
+            // splitmuxsink takes a single video stream
+            (true, "video") => "video".to_owned(),
+            (true, _) => "audio_%u".to_owned(),
+            (false, _) => self.output_format.pad_template(media),
+        }
+    }
+
+    /// Whether `encoding_name` audio gets recorded, as it does when the muxer takes it.
+    /// It isn't restreamed.
+    pub(crate) fn records_audio(&self, encoding_name: &str) -> bool {
+        self.record && !self.rtmp && self.output_format.takes_audio(encoding_name)
+    }
+}
+
+/// Elements the branch of each encoding needs, besides the video decoder and sink.
+pub(crate) const ENCODING_ELEMENTS: &[(&str, &[&str])] = &[
+    ("H264", &["rtph264depay", "h264parse"]),
+    ("H265", &["rtph265depay", "h265parse"]),
+    ("VP8", &["rtpvp8depay"]),
+    ("VP9", &["rtpvp9depay"]),
+    ("JPEG", &["rtpjpegdepay", "jpegparse"]),
+    ("AV1", &["rtpav1depay", "av1parse"]),
+    (
+        "MPEG4-GENERIC",
+        &[
+            "rtpmp4gdepay",
+            "aacparse",
+            "avdec_aac",
+            "audioconvert",
+            "audioresample",
+            "autoaudiosink",
+        ],
+    ),
+    (
+        "PCMU",
+        &[
+            "rtppcmudepay",
+            "mulawdec",
+            "audioconvert",
+            "audioresample",
+            "autoaudiosink",
+        ],
+    ),
+    (
+        "PCMA",
+        &[
+            "rtppcmadepay",
+            "alawdec",
+            "audioconvert",
+            "audioresample",
+            "autoaudiosink",
+        ],
+    ),
+    (
+        "OPUS",
+        &[
+            "rtpopusdepay",
+            "opusdec",
+            "audioconvert",
+            "audioresample",
+            "autoaudiosink",
+        ],
+    ),
+];
+
+/// Whether there's a branch for `encoding_name` streams other than a `fakesink`.
+pub(crate) fn supported(encoding_name: &str, opts: &BranchOptions) -> bool {
+    opts.overrides.contains_key(encoding_name)
+        || ENCODING_ELEMENTS.iter().any(|(e, _)| *e == encoding_name)
+}
+
+/// The elements branches need with `opts`, and those of `encoding_name` streams if given.
+pub(crate) fn elements(opts: &BranchOptions, encoding_name: Option<&str>) -> Vec<String> {
+    // e.g. `avdec_h264 max-threads=2`
+    let factory = |description: &str| {
+        description
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_owned()
+    };
+
+    let mut elements = vec!["appsrc".to_owned(), "rtpptdemux".to_owned()];
+
+    // With hwaccel, missing decoders fall back to others
+    let decoder = opts.hwaccel.is_none().then(|| factory(&opts.decoder));
+
+    if opts.snapshot.is_some() {
+        elements.extend(decoder.clone());
+        elements.extend(["videoconvert", "jpegenc", "filesink"].map(str::to_owned));
+    } else {
+        if opts.rtmp {
+            elements.extend(["flvmux", "rtmpsink"].map(str::to_owned));
+
+            if encoding_name.map_or(false, |e| e != "H264") {
+                elements.extend(decoder.clone());
+                elements.extend(["videoconvert", "x264enc", "h264parse"].map(str::to_owned));
+            }
+        } else if opts.record {
+            let sink = if opts.segmented {
+                "splitmuxsink"
+            } else {
+                "filesink"
+            };
+            elements.push(opts.output_format.muxer().to_owned());
+            elements.push(sink.to_owned());
+        }
+
+        if opts.headless {
+            if !opts.record {
+                elements.push("fakesink".to_owned());
+            }
+        } else {
+            if opts.record {
+                elements.push("tee".to_owned());
+            }
+
+            elements.push("queue".to_owned());
+
+            elements.extend(decoder.clone());
+            elements.push("videoconvert".to_owned());
+            elements.push(factory(&opts.video_sink));
+
+            if opts.window_title.is_some() {
+                elements.push("taginject".to_owned());
+            }
+        }
+    }
+
+    if opts.snapshot_interval.is_some() && opts.snapshot.is_none() {
+        elements
+            .extend(["tee", "queue", "videorate", "videoconvert", "jpegenc"].map(str::to_owned));
+
+        if opts.headless {
+            elements.extend(decoder);
+        }
+    }
+
+    if opts.dump_es.is_some() && opts.snapshot.is_none() {
+        elements.push("filesink".to_owned());
+
+        if !opts.headless {
+            elements.push("tee".to_owned());
+        }
+    }
+
+    if opts.jitter_latency.is_some() {
+        elements.push("rtpjitterbuffer".to_owned());
+    }
+
+    // What an overriding launch description needs is up to it
+    if let Some(encoding_name) = encoding_name.filter(|e| !opts.overrides.contains_key(*e)) {
+        let encoding = ENCODING_ELEMENTS.iter().find(|(e, _)| *e == encoding_name);
+
+        if let Some((_, encoding)) = encoding {
+            elements.extend(
+                encoding
+                    .iter()
+                    .filter(|e| !(opts.mute && **e == "autoaudiosink"))
+                    .map(|e| e.to_string()),
+            );
+        }
+
+        if opts.records_audio(encoding_name) {
+            elements.extend(["tee", "queue"].map(str::to_owned));
+        }
+    }
+
+    elements
+}
+
+/// The bin description for the pad `s` describes.
+///
+/// The parsers repeat the parameter sets before every keyframe, since some cameras only
+/// have them in the SDP, which the depayloaders only get to send once.
+pub(crate) fn launch(s: &gst::StructureRef, opts: &BranchOptions) -> String {
+    // Without one, it's a fakesink
+    let encoding_name = s.get::<&str>("encoding-name").unwrap_or_default();
+
+    let branch = match encoding_name {
+        _ if opts.overrides.contains_key(encoding_name) => opts.overrides[encoding_name].clone(),
+        "H264" => video(
+            "rtph264depay ! h264parse name=parse update-timecode=true config-interval=-1",
+            encoding_name,
+            opts,
+        ),
+        "H265" => video(
+            "rtph265depay ! h265parse name=parse config-interval=-1",
+            encoding_name,
+            opts,
+        ),
+        // No parsers for these; the depayloaders flag delta frames themselves
+        "VP8" => video("rtpvp8depay name=parse", encoding_name, opts),
+        "VP9" => video("rtpvp9depay name=parse", encoding_name, opts),
+        "JPEG" => video("rtpjpegdepay ! jpegparse", encoding_name, opts),
+        "AV1" => video("rtpav1depay ! av1parse name=parse", encoding_name, opts),
+        "MPEG4-GENERIC" => {
+            // rtpmp4gdepay doesn't always put these on its src caps, and aacparse
+            // needs them when the SDP `config` is missing.
+            let channels = s.get::<i32>("channels").unwrap_or(1);
+
+            // It's the sample rate, which there's no guessing
+            match s.get::<i32>("clock-rate") {
+                Ok(clock_rate) => audio(
+                    &format!(
+                        "rtpmp4gdepay \
+                        ! audio/mpeg,rate={},channels={} \
+                        ! aacparse",
+                        clock_rate, channels
+                    ),
+                    "avdec_aac",
+                    encoding_name,
+                    opts,
+                ),
+                Err(_) => {
+                    tracing::error!("MPEG4-GENERIC caps without a clock-rate; not playing it");
+                    "fakesink".to_owned()
+                }
+            }
+        }
+        "PCMU" => g711("rtppcmudepay", "audio/x-mulaw", "mulawdec", s, opts),
+        "PCMA" => g711("rtppcmadepay", "audio/x-alaw", "alawdec", s, opts),
+        "OPUS" => {
+            // The SDP always says 2 channels for Opus, so have opusdec mix to that
+            let channels = s.get::<i32>("channels").unwrap_or(2);
+
+            audio(
+                "rtpopusdepay",
+                &format!("opusdec ! audio/x-raw,channels={}", channels),
+                encoding_name,
+                opts,
+            )
+        }
+        _ => "fakesink".to_owned(),
+    };
+
+    // Per branch, since a jitter buffer takes a single stream
+    match opts.jitter_latency {
+        Some(latency) => format!(
+            "rtpjitterbuffer latency={} ! {}",
+            latency.as_millis(),
+            branch
+        ),
+        None => branch,
+    }
+}
+
+/// [launch], then what to fall back to if that can't be made, e.g. without the
+/// hardware decoder's plugin: software decoders, then a `fakesink`.
+pub(crate) fn launches(s: &gst::StructureRef, opts: &BranchOptions) -> Vec<String> {
+    let software = BranchOptions {
+        hwaccel: Some(Hwaccel::None),
+        ..opts.clone()
+    };
+
+    let mut launches = vec![launch(s, opts), launch(s, &software), "fakesink".to_owned()];
+    launches.dedup();
+
+    launches
+}
+
+/// A G.711 branch; these hardly ever run at other than 8 kHz mono, but the SDP says.
+fn g711(
+    depay: &str,
+    media_type: &str,
+    dec: &str,
+    s: &gst::StructureRef,
+    opts: &BranchOptions,
+) -> String {
+    let clock_rate = s.get::<i32>("clock-rate").unwrap_or(8000);
+    let channels = s.get::<i32>("channels").unwrap_or(1);
+    let encoding_name = s.get::<&str>("encoding-name").unwrap_or_default();
+
+    audio(
+        &format!(
+            "{} ! {},rate={},channels={}",
+            depay, media_type, clock_rate, channels
+        ),
+        dec,
+        encoding_name,
+        opts,
+    )
+}
+
+/// An audio branch decoding the `parsed` audio with `dec` and playing it, and
+/// recording it too if the muxer takes `encoding_name` audio.
+fn audio(parsed: &str, dec: &str, encoding_name: &str, opts: &BranchOptions) -> String {
+    let played = format!(
+        "{} \
+        ! audioconvert \
+        ! audioresample \
+        ! {} \
+        ! {}",
+        dec,
+        sink_queue(opts),
+        audio_sink(opts)
+    );
+
+    if opts.records_audio(encoding_name) {
+        // The queue left unlinked is the one the muxer gets
+        format!(
+            "{} \
+            ! tee name=t \
+            t. ! queue ! {} \
+            t. ! queue",
+            parsed, played
+        )
+    } else {
+        format!("{} ! {}", parsed, played)
+    }
+}
+
+/// The element playing audio; a `fakesink` that keeps to the clock when muted.
+fn audio_sink(opts: &BranchOptions) -> &'static str {
+    if opts.mute {
+        "fakesink sync=true"
+    } else {
+        "autoaudiosink"
+    }
+}
+
+/// The queue before a sink, so a render stall doesn't hold up depaying.
+fn sink_queue(opts: &BranchOptions) -> String {
+    if opts.low_latency {
+        return "queue max-size-buffers=1 max-size-bytes=0 max-size-time=0 leaky=downstream"
+            .to_owned();
+    }
+
+    match opts.queue_size {
+        Some(size) => format!("queue max-size-time={}", size.as_nanos()),
+        None => "queue".to_owned(),
+    }
+}
+
+/// Finishes a video branch that starts with `depay`, of `encoding_name` video.
+fn video(depay: &str, encoding_name: &str, opts: &BranchOptions) -> String {
+    let decoder = opts
+        .hwaccel
+        .and_then(|hwaccel| hw_decoder(hwaccel, encoding_name))
+        .unwrap_or(opts.decoder.as_str());
+
+    // FLV only takes H.264 of these, so the rest get re-encoded
+    let muxed = if opts.rtmp && encoding_name != "H264" {
+        format!(
+            " ! {} ! videoconvert ! x264enc tune=zerolatency ! h264parse",
+            decoder
+        )
+    } else {
+        String::new()
+    };
+
+    // H.265 in Annex B, what tools take raw files in; the parser converts
+    let dump = match (&opts.dump_es, encoding_name) {
+        (Some(path), "H264") => Some(format!(
+            "video/x-h264,stream-format={},alignment=au ! filesink location=\"{}\"",
+            opts.h264_format.stream_format(),
+            path.display()
+        )),
+        (Some(path), "H265") => Some(format!(
+            "video/x-h265,stream-format=byte-stream ! filesink location=\"{}\"",
+            path.display()
+        )),
+        _ => None,
+    };
+
+    // What the decoded video goes to, if it's decoded at all
+    let shown = match (opts.headless, thumbnails(opts)) {
+        (false, thumbnails) => Some(display(opts, thumbnails)),
+        (true, Some(thumbnails)) => Some(thumbnails),
+        (true, None) => None,
+    };
+
+    if let Some(path) = &opts.snapshot {
+        format!(
+            "{} \
+            ! {} \
+            ! videoconvert name=convert \
+            ! jpegenc snapshot=true \
+            ! filesink location=\"{}\"",
+            depay,
+            decoder,
+            path.display()
+        )
+    } else if let Some(dump) = dump {
+        match shown {
+            Some(shown) => format!(
+                "{} \
+                ! tee name=t \
+                t. ! queue ! {} ! videoconvert name=convert ! {} \
+                t. ! queue ! {}",
+                depay, decoder, shown, dump
+            ),
+            None => format!("{} ! {}", depay, dump),
+        }
+    } else if opts.record {
+        match shown {
+            // The queue left unlinked is the one the muxer gets
+            Some(shown) => format!(
+                "{} \
+                ! tee name=t \
+                t. ! queue ! {} ! videoconvert name=convert ! {} \
+                t. ! queue{}",
+                depay, decoder, shown, muxed
+            ),
+            None => format!("{}{}", depay, muxed),
+        }
+    } else {
+        match shown {
+            Some(shown) => format!(
+                "{} \
+                ! {} \
+                ! videoconvert name=convert \
+                ! {}",
+                depay, decoder, shown
+            ),
+            None => format!("{} ! fakesink sync=false", depay),
+        }
+    }
+}
+
+/// A JPEG of the decoded video every [BranchOptions::snapshot_interval], which
+/// link_branch saves, if asked for.
+fn thumbnails(opts: &BranchOptions) -> Option<String> {
+    let (interval, _, _) = opts.snapshot_interval.as_ref()?;
+
+    Some(format!(
+        "queue leaky=downstream max-size-buffers=1 \
+        ! videorate drop-only=true \
+        ! video/x-raw,framerate=1000/{} \
+        ! videoconvert \
+        ! jpegenc name=thumbnail \
+        ! fakesink sync=false async=false",
+        interval.as_millis().max(1)
+    ))
+}
+
+/// The end of a video branch that displays it, titling the window if asked to, and
+/// tees it to `thumbnails` if given.
+fn display(opts: &BranchOptions, thumbnails: Option<String>) -> String {
+    // link_branch sets the title; there's no quoting it safely here
+    let title = match opts.window_title {
+        Some(_) => "taginject name=title ! ",
+        None => "",
+    };
+
+    // on_first_frame watches what leaves the queue
+    let display = format!(
+        "{}{} name=display ! {}",
+        title,
+        sink_queue(opts),
+        opts.video_sink
+    );
+
+    match thumbnails {
+        Some(thumbnails) => format!("tee name=thumbs ! {} thumbs. ! {}", display, thumbnails),
+        None => display,
+    }
+}
+
+/// The `hwaccel` decoder of `encoding_name` video, if it's installed.
+fn hw_decoder(hwaccel: Hwaccel, encoding_name: &str) -> Option<&'static str> {
+    let decoder = match (hwaccel, encoding_name) {
+        (Hwaccel::Auto, _) => {
+            return [Hwaccel::Vaapi, Hwaccel::Nvdec, Hwaccel::V4l2, Hwaccel::None]
+                .into_iter()
+                .find_map(|hwaccel| hw_decoder(hwaccel, encoding_name));
+        }
+        (Hwaccel::Vaapi, _) => "vaapidecodebin",
+        (Hwaccel::Nvdec, "H264") => "nvh264dec",
+        (Hwaccel::Nvdec, "H265") => "nvh265dec",
+        (Hwaccel::Nvdec, "VP8") => "nvvp8dec",
+        (Hwaccel::Nvdec, "VP9") => "nvvp9dec",
+        (Hwaccel::Nvdec, "JPEG") => "nvjpegdec",
+        (Hwaccel::Nvdec, "AV1") => "nvav1dec",
+        (Hwaccel::V4l2, "H264") => "v4l2h264dec",
+        (Hwaccel::V4l2, "H265") => "v4l2h265dec",
+        (Hwaccel::V4l2, "VP8") => "v4l2vp8dec",
+        (Hwaccel::V4l2, "VP9") => "v4l2vp9dec",
+        (Hwaccel::V4l2, "JPEG") => "v4l2jpegdec",
+        (Hwaccel::None, "H264") => "avdec_h264",
+        (Hwaccel::None, "H265") => "avdec_h265",
+        (Hwaccel::None, "VP8") => "vp8dec",
+        (Hwaccel::None, "VP9") => "vp9dec",
+        (Hwaccel::None, "JPEG") => "jpegdec",
+        // dav1d is much faster than libaom
+        (Hwaccel::None, "AV1") => {
+            return ["dav1ddec", "av1dec"]
+                .into_iter()
+                .find(|decoder| gst::ElementFactory::find(decoder).is_some());
+        }
+        _ => return None,
+    };
+
+    gst::ElementFactory::find(decoder).map(|_| decoder)
+}
+
+/// Logs the caps each src pad in `bin` negotiates, including pads added later,
+/// e.g. by `decodebin`.
+pub(crate) fn log_caps(bin: &gst::Bin) {
+    for element in bin.iterate_recurse().into_iter().flatten() {
+        log_element_caps(&element);
+    }
+}
+
+fn log_element_caps(element: &gst::Element) {
+    for pad in element.src_pads() {
+        log_pad_caps(&pad);
+    }
+
+    element.connect_pad_added(|_, pad| {
+        if pad.direction() == gst::PadDirection::Src {
+            log_pad_caps(pad);
+        }
+    });
+
+    // e.g. the elements decodebin plugs in
+    if let Some(bin) = element.downcast_ref::<gst::Bin>() {
+        bin.connect_element_added(|_, element| log_element_caps(element));
+    }
+}
+
+fn log_pad_caps(pad: &gst::Pad) {
+    pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, |pad, info| {
+        if let Some(gst::PadProbeData::Event(event)) = &info.data {
+            if let gst::EventView::Caps(caps) = event.view() {
+                tracing::info!("{}: {}", pad.path_string(), caps.caps());
+            }
+        }
+
+        gst::PadProbeReturn::Ok
+    });
+}
+
+/// Logs the size and frame rate `bin`'s decoder outputs, which can differ from what
+/// the camera is configured to send; again whenever they change.
+pub(crate) fn log_video_format(bin: &gst::Bin) {
+    let sink = match bin
+        .by_name("convert")
+        .and_then(|convert| convert.static_pad("sink"))
+    {
+        Some(sink) => sink,
+        None => return,
+    };
+
+    let logged = Mutex::new(None);
+
+    sink.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |pad, info| {
+        if let Some(gst::PadProbeData::Event(event)) = &info.data {
+            if let gst::EventView::Caps(caps) = event.view() {
+                if let Ok(video) = gst_video::VideoInfo::from_caps(caps.caps()) {
+                    let format = (video.width(), video.height(), video.fps());
+
+                    if logged.lock().unwrap().replace(format) != Some(format) {
+                        let (width, height, fps) = format;
+                        let fps = match fps.numer() {
+                            0 => "variable".to_owned(),
+                            _ => format!("{:.2}", f64::from(fps.numer()) / f64::from(fps.denom())),
+                        };
+
+                        tracing::info!(
+                            "{}: decoding {}x{} at {} fps",
+                            pad.path_string(),
+                            width,
+                            height,
+                            fps
+                        );
+                    }
+                }
+            }
+        }
+
+        gst::PadProbeReturn::Ok
+    });
+}
+
+/// Titles the window of the sink in `bin` `title`, through its `taginject`.
+pub(crate) fn set_window_title(bin: &gst::Bin, title: &str) {
+    if let Some(taginject) = bin.by_name("title") {
+        let title = title.replace('\\', "\\\\").replace('"', "\\\"");
+        taginject.set_property("tags", format!("title=\"{}\"", title));
+    }
+}
+
+/// Has the video overlays in `bin` render in `geometry`, including ones added later,
+/// e.g. by `autovideosink`. `glimagesink` moves and sizes its window to it; others
+/// only draw in that part of theirs.
+pub(crate) fn set_window_geometry(bin: &gst::Bin, geometry: WindowGeometry) {
+    let place = move |element: &gst::Element| {
+        if let Some(overlay) = element.dynamic_cast_ref::<gst_video::VideoOverlay>() {
+            let WindowGeometry {
+                width,
+                height,
+                x,
+                y,
+            } = geometry;
+
+            if let Err(err) = overlay.set_render_rectangle(x, y, width as i32, height as i32) {
+                tracing::warn!("Can't place {}'s window: {}", element.name(), err);
+            }
+        }
+    };
+
+    for element in bin.iterate_recurse().into_iter().flatten() {
+        place(&element);
+    }
+
+    bin.connect_deep_element_added(move |_, _, element| place(element));
+}
+
+/// Pushes `pad`'s buffers to its peer itself, returning OK whatever that returns, so a
+/// branch's error never goes upstream to stop appsrc before the branch is restarted.
+/// Drops them while the branch is unlinked for restarting.
+pub(crate) fn isolate_errors(pad: &gst::Pad) {
+    pad.add_probe(gst::PadProbeType::BUFFER, |pad, info| {
+        let peer = match pad.peer() {
+            Some(peer) => peer,
+            None => return gst::PadProbeReturn::Drop,
+        };
+
+        if let Some(gst::PadProbeData::Buffer(buffer)) = info.data.take() {
+            if let Err(err) = peer.chain(buffer) {
+                tracing::trace!("Ignoring {:?} from {}", err, pad.name());
+            }
+        }
+
+        gst::PadProbeReturn::Handled
+    });
+}
+
+/// Has the sinks in `bin` render buffers as they come instead of syncing them to the
+/// clock, including sinks added later, e.g. by `autovideosink`.
+pub(crate) fn unsync_sinks(bin: &gst::Bin) {
+    let unsync = |element: &gst::Element| {
+        if element.element_flags().contains(gst::ElementFlags::SINK)
+            && element.find_property("sync").is_some()
+        {
+            element.set_property("sync", false);
+        }
+    };
+
+    for element in bin.iterate_recurse().into_iter().flatten() {
+        unsync(&element);
+    }
+
+    bin.connect_deep_element_added(move |_, _, element| unsync(element));
+}
+
+/// Logs when `bin`'s parser passes the first keyframe, which video can't show before.
+///
+/// There's no asking the camera for one sooner: Retina only receives RTCP, and sends
+/// none, so no PLI or FIR.
+pub(crate) fn log_first_keyframe(bin: &gst::Bin) {
+    let src = match bin
+        .by_name("parse")
+        .and_then(|parse| parse.static_pad("src"))
+    {
+        Some(src) => src,
+        None => return,
+    };
+
+    let added = Instant::now();
+    let deltas = AtomicU64::new(0);
+
+    src.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+        match &info.data {
+            Some(gst::PadProbeData::Buffer(buffer))
+                if buffer.flags().contains(gst::BufferFlags::DELTA_UNIT) =>
+            {
+                deltas.fetch_add(1, Ordering::Relaxed);
+                gst::PadProbeReturn::Ok
+            }
+            _ => {
+                tracing::info!(
+                    "{}: first keyframe after {:?} and {} delta frames",
+                    pad.path_string(),
+                    added.elapsed(),
+                    deltas.load(Ordering::Relaxed)
+                );
+                gst::PadProbeReturn::Remove
+            }
+        }
+    });
+}
+
+/// Logs when the first frame of `bin` goes to its video sink, and calls `f`.
+pub(crate) fn on_first_frame(bin: &gst::Bin, f: Option<Arc<dyn Fn() + Send + Sync>>) {
+    let src = match bin
+        .by_name("display")
+        .and_then(|queue| queue.static_pad("src"))
+    {
+        Some(src) => src,
+        None => return,
+    };
+
+    let added = Instant::now();
+
+    src.add_probe(gst::PadProbeType::BUFFER, move |pad, _| {
+        tracing::info!(
+            "{}: first frame to the sink after {:?}",
+            pad.path_string(),
+            added.elapsed()
+        );
+
+        if let Some(f) = &f {
+            f();
+        }
+
+        gst::PadProbeReturn::Remove
+    });
+}
+
+/// Keeps `last` at when the latest frame of `bin` went to its video sink, from now.
+pub(crate) fn watch_frames(bin: &gst::Bin, last: Arc<Mutex<Option<Instant>>>) {
+    let src = match bin
+        .by_name("display")
+        .and_then(|queue| queue.static_pad("src"))
+    {
+        Some(src) => src,
+        None => return,
+    };
+
+    *last.lock().unwrap() = Some(Instant::now());
+
+    src.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+        *last.lock().unwrap() = Some(Instant::now());
+        gst::PadProbeReturn::Ok
+    });
+}
+
+/// Saves the JPEGs of `bin`'s thumbnail encoder to `dir`, named `prefix` and the Unix
+/// time.
+pub(crate) fn save_thumbnails(bin: &gst::Bin, dir: PathBuf, prefix: String) {
+    let src = match bin
+        .by_name("thumbnail")
+        .and_then(|jpegenc| jpegenc.static_pad("src"))
+    {
+        Some(src) => src,
+        None => return,
+    };
+
+    if let Err(err) = std::fs::create_dir_all(&dir) {
+        tracing::warn!("Can't make {}: {}", dir.display(), err);
+    }
+
+    src.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+        if let Some(gst::PadProbeData::Buffer(buffer)) = &info.data {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let path = dir.join(format!(
+                "{}{}.{:03}.jpg",
+                prefix,
+                now.as_secs(),
+                now.subsec_millis()
+            ));
+
+            let res = match buffer.map_readable() {
+                Ok(jpeg) => std::fs::write(&path, jpeg.as_slice()).map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            };
+
+            match res {
+                Ok(()) => tracing::debug!("Saved {}", path.display()),
+                Err(err) => tracing::warn!("Can't save {}: {}", path.display(), err),
+            }
+        }
+
+        gst::PadProbeReturn::Ok
+    });
+}
+
+/// Drops the buffers of `bin`'s parser until the first keyframe, so e.g. a snapshot
+/// isn't of a half decoded frame.
+pub(crate) fn wait_for_keyframe(bin: &gst::Bin) {
+    let src = match bin
+        .by_name("parse")
+        .and_then(|parse| parse.static_pad("src"))
+    {
+        Some(src) => src,
+        None => return,
+    };
+
+    src.add_probe(gst::PadProbeType::BUFFER, |_, info| match &info.data {
+        Some(gst::PadProbeData::Buffer(buffer))
+            if buffer.flags().contains(gst::BufferFlags::DELTA_UNIT) =>
+        {
+            gst::PadProbeReturn::Drop
+        }
+        _ => gst::PadProbeReturn::Remove,
+    });
+}
@@ -0,0 +1,166 @@
+//! Plays a recorded file through the same branches as a session, for testing the
+//! decoding and display without a camera.
+
+use std::future::Future;
+use std::path::Path;
+
+use futures_util::StreamExt;
+
+use gst::prelude::*;
+
+use color_eyre::{eyre::eyre, Result};
+
+use crate::{
+    add_branch, add_recording_muxer, branch, branch_options, finish_recording, PlayerOptions,
+};
+
+/// Plays the MP4 or MKV file at `path` until it ends, or forever if `looping`, or until
+/// `stop` resolves; the recording then gets finished, as [crate::RtsPlayer::stop] does.
+///
+/// Each of the file's streams gets payloaded into RTP and goes through the branch a
+/// session's stream of that codec would.
+pub async fn play_file(
+    path: &Path,
+    opts: &PlayerOptions,
+    looping: bool,
+    stop: impl Future<Output = ()>,
+) -> Result<()> {
+    gst::init()?;
+
+    let pipeline = gst::Pipeline::new(opts.name.as_deref());
+
+    add_recording_muxer(&pipeline, opts, 0)?;
+
+    let filesrc = gst::ElementFactory::make("filesrc", None)?;
+    filesrc.set_property("location", &*path.to_string_lossy());
+
+    let parsebin = gst::ElementFactory::make("parsebin", None)?;
+
+    // A file isn't late or out of order
+    let mut branch = branch_options(opts);
+    branch.jitter_latency = None;
+
+    let pipeline_weak = pipeline.downgrade();
+    parsebin.connect_pad_added(move |_, pad| {
+        let caps = match pad.current_caps().or_else(|| pad.caps()) {
+            Some(caps) => caps,
+            None => return,
+        };
+
+        let (pay, s) = match caps.structure(0).and_then(rtp_structure) {
+            Some(rtp) => rtp,
+            None => {
+                tracing::info!("Not playing the file's {:?} stream", caps);
+                return;
+            }
+        };
+
+        if let Some(pipeline) = pipeline_weak.upgrade() {
+            let media = s.get::<&str>("media").unwrap_or_default();
+            let launches: Vec<_> = branch::launches(&s, &branch)
+                .into_iter()
+                .map(|launch| format!("{} ! {}", pay, launch))
+                .collect();
+
+            if let Err(err) = add_branch(&pipeline, pad, &launches, media, &branch) {
+                tracing::error!(
+                    "Can't add the branch of the file's {} stream: {:#}",
+                    media,
+                    err
+                );
+            }
+        }
+    });
+
+    pipeline.add_many(&[&filesrc, &parsebin])?;
+    gst::Element::link_many(&[&filesrc, &parsebin])?;
+
+    let mut bus_stream = pipeline.bus().unwrap().stream();
+
+    pipeline.set_state(gst::State::Playing)?;
+
+    tokio::pin!(stop);
+
+    // When to stop waiting for EOS, once stopping
+    let mut deadline = None;
+
+    let res = loop {
+        let msg = tokio::select! {
+            msg = bus_stream.next() => match msg {
+                Some(msg) => msg,
+                None => break Ok(()),
+            },
+            () = &mut stop, if deadline.is_none() => {
+                tracing::info!("Stopping, finishing the file");
+
+                pipeline.send_event(gst::event::Eos::new());
+                deadline = Some(tokio::time::Instant::now() + opts.teardown_timeout);
+                continue;
+            }
+            _ = async { tokio::time::sleep_until(deadline.unwrap()).await }, if deadline.is_some() => {
+                tracing::warn!("No EOS after {:?}, not waiting for it", opts.teardown_timeout);
+                break Ok(());
+            }
+        };
+
+        use gst::MessageView;
+
+        match msg.view() {
+            MessageView::Eos(_) if looping && deadline.is_none() => {
+                tracing::info!("Looping {}", path.display());
+
+                if let Err(err) = pipeline.seek_simple(
+                    gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT,
+                    gst::ClockTime::ZERO,
+                ) {
+                    break Err(eyre!("Can't seek back to the start: {}", err));
+                }
+            }
+            MessageView::Eos(_) => break Ok(()),
+            MessageView::Error(err) => break Err(eyre!(err.error())),
+            _ => {}
+        }
+    };
+
+    if res.is_err() {
+        if let Some(mux) = pipeline.by_name("recmux") {
+            finish_recording(&pipeline, &mux, &mut bus_stream, opts.teardown_timeout).await;
+        }
+    }
+
+    pipeline.set_state(gst::State::Null)?;
+
+    res
+}
+
+/// The payloader for a stream of the file with caps `s`, and the RTP caps it makes.
+fn rtp_structure(s: &gst::StructureRef) -> Option<(&'static str, gst::Structure)> {
+    let (pay, media, encoding_name, clock_rate) = match s.name() {
+        "video/x-h264" => ("rtph264pay config-interval=-1", "video", "H264", 90000),
+        "video/x-h265" => ("rtph265pay config-interval=-1", "video", "H265", 90000),
+        "video/x-vp8" => ("rtpvp8pay", "video", "VP8", 90000),
+        "video/x-vp9" => ("rtpvp9pay", "video", "VP9", 90000),
+        "image/jpeg" => ("rtpjpegpay", "video", "JPEG", 90000),
+        "video/x-av1" => ("rtpav1pay", "video", "AV1", 90000),
+        "audio/mpeg" if s.get::<i32>("mpegversion").ok() == Some(4) => (
+            "rtpmp4gpay",
+            "audio",
+            "MPEG4-GENERIC",
+            s.get::<i32>("rate").ok()?,
+        ),
+        "audio/x-opus" => ("rtpopuspay", "audio", "OPUS", 48000),
+        _ => return None,
+    };
+
+    let mut rtp = gst::Structure::builder("application/x-rtp")
+        .field("media", media)
+        .field("encoding-name", encoding_name)
+        .field("clock-rate", clock_rate)
+        .build();
+
+    if let Ok(channels) = s.get::<i32>("channels") {
+        rtp.set("channels", channels);
+    }
+
+    Some((pay, rtp))
+}
@@ -0,0 +1,140 @@
+//! Frame rates of video streams by their RTP timestamps, against the SDP's or the first
+//! measured.
+
+use std::collections::HashMap;
+
+/// RTP time to measure each frame rate over, in seconds.
+const WINDOW_SECS: f64 = 10.0;
+
+/// How far off the expected a frame rate can be before it gets logged, as a fraction of
+/// it.
+const MAX_DRIFT: f64 = 0.2;
+
+/// Counts the frames of the video streams, warning when the rate they arrive at stays
+/// off what the SDP's `a=framerate` says, e.g. a camera claiming 30 fps but sending 12
+/// under load. Without one, the rate of the first window is what's expected.
+#[derive(Debug, Default)]
+pub(crate) struct FrameRates {
+    expected: HashMap<usize, Expected>,
+    windows: HashMap<usize, Window>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Expected {
+    Declared(f64),
+    /// No `a=framerate`, so it's measured over the first window.
+    Learning,
+    Learned(f64),
+}
+
+#[derive(Debug)]
+struct Window {
+    /// RTP timestamp of the frame the window started at.
+    start: i64,
+    /// The latest RTP timestamp, and the previous packet's.
+    latest: i64,
+    last: i64,
+    /// Frames since the start.
+    frames: u64,
+    drifting: bool,
+}
+
+impl FrameRates {
+    /// The declared frame rates of the video streams in `sdp`, by stream index.
+    pub(crate) fn new(sdp: &[u8]) -> Self {
+        let mut expected = HashMap::new();
+        let mut stream = None;
+
+        for line in String::from_utf8_lossy(sdp).lines() {
+            if line.starts_with("m=") {
+                let i = stream.map_or(0, |i| i + 1);
+                stream = Some(i);
+
+                if line.starts_with("m=video") {
+                    expected.insert(i, Expected::Learning);
+                }
+            }
+
+            let fps = line
+                .strip_prefix("a=framerate:")
+                .and_then(|fps| fps.trim().parse::<f64>().ok())
+                .filter(|&fps| fps > 0.0);
+
+            // A session level one doesn't say which stream it's of
+            if let (Some(i), Some(fps)) = (stream, fps) {
+                expected.insert(i, Expected::Declared(fps));
+            }
+        }
+
+        FrameRates {
+            expected,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Counts the packet with RTP timestamp `ts` of `stream_id`; a frame's packets share
+    /// one.
+    pub(crate) fn packet(&mut self, stream_id: usize, ts: retina::Timestamp) {
+        let expected = match self.expected.get(&stream_id) {
+            Some(&expected) => expected,
+            None => return,
+        };
+
+        let ts_value = ts.timestamp();
+        let window = self.windows.entry(stream_id).or_insert(Window {
+            start: ts_value,
+            latest: ts_value,
+            last: ts_value,
+            frames: 0,
+            drifting: false,
+        });
+
+        if ts_value == window.last {
+            return;
+        }
+
+        // B-frames come out of order, but still one timestamp each
+        window.last = ts_value;
+        window.latest = window.latest.max(ts_value);
+        window.frames += 1;
+
+        let secs = (window.latest - window.start) as f64 / f64::from(ts.clock_rate().get());
+        if secs < WINDOW_SECS {
+            return;
+        }
+
+        let fps = window.frames as f64 / secs;
+        window.start = window.latest;
+        window.frames = 0;
+
+        let (expected, source) = match expected {
+            Expected::Declared(expected) => (expected, "the SDP says"),
+            Expected::Learned(expected) => (expected, "it started at"),
+            Expected::Learning => {
+                tracing::info!(
+                    stream_id,
+                    "Video at {:.1} fps; the SDP has no a=framerate, so that's what's expected",
+                    fps
+                );
+                self.expected.insert(stream_id, Expected::Learned(fps));
+                return;
+            }
+        };
+
+        let drifting = (fps - expected).abs() > expected * MAX_DRIFT;
+
+        if drifting && !window.drifting {
+            tracing::warn!(
+                stream_id,
+                "Video at {:.1} fps, but {} {:.1} fps",
+                fps,
+                source,
+                expected
+            );
+        } else if !drifting && window.drifting {
+            tracing::info!(stream_id, "Video back at {:.1} fps", fps);
+        }
+
+        window.drifting = drifting;
+    }
+}
@@ -0,0 +1,65 @@
+//! Drops and reorders RTP packets on purpose, reproducibly, for testing how the
+//! pipeline copes with a bad network.
+
+use std::collections::HashMap;
+
+/// Impairs the packets pushed to appsrc by [Impair::packet], with a seeded generator so
+/// a seed always impairs the same packets of the same session.
+#[derive(Debug)]
+pub(crate) struct Impair {
+    state: u64,
+    loss: f64,
+    reorder: f64,
+    /// Each stream's packet held back to go after its next one.
+    held: HashMap<usize, gst::Buffer>,
+}
+
+impl Impair {
+    /// Drops `loss` and delays `reorder` of the packets, as fractions; `None` if there's
+    /// nothing to do.
+    pub(crate) fn new(loss: f64, reorder: f64, seed: u64) -> Option<Self> {
+        if loss <= 0.0 && reorder <= 0.0 {
+            return None;
+        }
+
+        tracing::warn!(loss, reorder, seed, "Impairing RTP packets for testing");
+
+        Some(Impair {
+            // xorshift is stuck at 0
+            state: seed.wrapping_add(0x9e37_79b9_7f4a_7c15).max(1),
+            loss,
+            reorder,
+            held: HashMap::new(),
+        })
+    }
+
+    /// The buffers to push, in order, for the packet `buffer` of `stream_id`.
+    ///
+    /// A delayed packet goes after the stream's next, so it's never pushed with another
+    /// stream's caps.
+    pub(crate) fn packet(&mut self, stream_id: usize, buffer: gst::Buffer) -> Vec<gst::Buffer> {
+        if self.roll() < self.loss {
+            return Vec::new();
+        }
+
+        if let Some(held) = self.held.remove(&stream_id) {
+            return vec![buffer, held];
+        }
+
+        if self.roll() < self.reorder {
+            self.held.insert(stream_id, buffer);
+            return Vec::new();
+        }
+
+        vec![buffer]
+    }
+
+    /// The next number in `[0, 1)`, by xorshift64.
+    fn roll(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,2845 @@
+//! Plays RTSP streams through GStreamer, with Retina as the RTSP client.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::num::{NonZeroU16, NonZeroU32};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use retina::client::*;
+use tracing::Instrument;
+
+use gst::prelude::*;
+
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use color_eyre::{
+    eyre::{bail, eyre},
+    Result,
+};
+
+mod branch;
+mod file;
+mod framerate;
+mod impair;
+mod pcap;
+mod stats;
+mod status;
+mod timestamps;
+
+use branch::BranchOptions;
+use framerate::FrameRates;
+use impair::Impair;
+use stats::{BranchCounters, Stats};
+use timestamps::Timestamps;
+
+pub use file::play_file;
+pub use pcap::PcapWriter;
+pub use stats::{BranchStats, StreamStats};
+pub use status::{serve_metrics, serve_status};
+
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+pub enum Codec {
+    /// Use the first supported video stream.
+    Auto,
+    H264,
+    H265,
+    Vp8,
+    Vp9,
+    /// Motion JPEG.
+    Mjpeg,
+    Av1,
+}
+
+impl Codec {
+    /// Retina's `encoding_name` of the preferred codec.
+    fn encoding_name(self) -> Option<&'static str> {
+        match self {
+            Codec::Auto => None,
+            Codec::H264 => Some("h264"),
+            Codec::H265 => Some("h265"),
+            Codec::Vp8 => Some("vp8"),
+            Codec::Vp9 => Some("vp9"),
+            Codec::Mjpeg => Some("jpeg"),
+            Codec::Av1 => Some("av1"),
+        }
+    }
+}
+
+/// Which decoder elements to use for video.
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+pub enum Hwaccel {
+    /// The first of the others whose decoders are installed.
+    Auto,
+    /// `vaapidecodebin`, on Intel and AMD.
+    Vaapi,
+    /// `nvh264dec` and friends, on NVIDIA.
+    Nvdec,
+    /// `v4l2h264dec` and friends, e.g. on a Raspberry Pi.
+    V4l2,
+    /// Software decoders, e.g. `avdec_h264`.
+    None,
+}
+
+/// Address family to reach the camera over.
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+pub enum IpFamily {
+    /// Whichever Retina connects to, the first address the host resolves to.
+    Auto,
+    V4,
+    V6,
+}
+
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+pub enum TransportKind {
+    /// On ports Retina picks; there's no pinning them to a range, so through a firewall
+    /// use TCP.
+    ///
+    /// Nor is there sizing their receive buffers: they get the system default
+    /// (`net.core.rmem_default` on Linux), which high bitrate streams can overflow. Raise
+    /// that, use TCP, or pick a substream with [PlayerOptions::stream_control].
+    Udp,
+    /// Interleaved in the RTSP connection.
+    Tcp,
+}
+
+/// What to do with the `rtptime` of the PLAY response's `RTP-Info` header, which
+/// gives each stream's first RTP timestamp.
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+pub enum InitialTimestamp {
+    /// Retina's default: use it when every stream has one.
+    Default,
+    /// Fail the PLAY without one.
+    Require,
+    /// Start from the first packet's timestamp instead; for cameras whose `rtptime`
+    /// doesn't match their packets, so the first timestamps jump.
+    Ignore,
+    /// Use it for whichever streams have one.
+    Permissive,
+}
+
+impl From<InitialTimestamp> for InitialTimestampPolicy {
+    fn from(initial_timestamp: InitialTimestamp) -> Self {
+        match initial_timestamp {
+            InitialTimestamp::Default => InitialTimestampPolicy::Default,
+            InitialTimestamp::Require => InitialTimestampPolicy::Require,
+            InitialTimestamp::Ignore => InitialTimestampPolicy::Ignore,
+            InitialTimestamp::Permissive => InitialTimestampPolicy::Permissive,
+        }
+    }
+}
+
+/// Size, and position if given, of the video window: `WxH` or `WxH+X+Y`.
+#[derive(Debug, Clone, Copy)]
+pub struct WindowGeometry {
+    pub width: u32,
+    pub height: u32,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl std::str::FromStr for WindowGeometry {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let bad = || format!("{:?} isn't WxH or WxH+X+Y", s);
+
+        let (size, position) = match s.split_once('+') {
+            Some((size, position)) => (size, Some(position)),
+            None => (s, None),
+        };
+
+        let (width, height) = size.split_once('x').ok_or_else(bad)?;
+        let (x, y) = match position {
+            Some(position) => position.split_once('+').ok_or_else(bad)?,
+            None => ("0", "0"),
+        };
+
+        Ok(WindowGeometry {
+            width: width.parse().map_err(|_| bad())?,
+            height: height.parse().map_err(|_| bad())?,
+            x: x.parse().map_err(|_| bad())?,
+            y: y.parse().map_err(|_| bad())?,
+        })
+    }
+}
+
+/// Container of [PlayerOptions::record].
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+pub enum OutputFormat {
+    /// `mp4mux`; unplayable if not finalized, e.g. when killed.
+    Mp4,
+    /// `matroskamux`.
+    Mkv,
+    /// `mpegtsmux`; no VP8, VP9, Motion JPEG or AV1.
+    Ts,
+}
+
+impl OutputFormat {
+    /// Element muxing this format.
+    pub(crate) fn muxer(self) -> &'static str {
+        match self {
+            OutputFormat::Mp4 => "mp4mux",
+            OutputFormat::Mkv => "matroskamux",
+            OutputFormat::Ts => "mpegtsmux",
+        }
+    }
+
+    /// Template of the muxer's request pads for `media` streams.
+    pub(crate) fn pad_template(self, media: &str) -> String {
+        match self {
+            OutputFormat::Mp4 | OutputFormat::Mkv => format!("{}_%u", media),
+            OutputFormat::Ts => "sink_%d".to_owned(),
+        }
+    }
+
+    /// Whether the muxer takes parsed `encoding_name` audio; mp4mux and mpegtsmux have
+    /// no G.711.
+    pub(crate) fn takes_audio(self, encoding_name: &str) -> bool {
+        match encoding_name {
+            "MPEG4-GENERIC" | "OPUS" => true,
+            "PCMU" | "PCMA" => matches!(self, OutputFormat::Mkv),
+            _ => false,
+        }
+    }
+}
+
+/// Stream format of [PlayerOptions::dump_es] H.264. Recordings get what their muxer
+/// takes.
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+pub enum H264Format {
+    /// Length prefixed NAL units, with the SPS and PPS in-band before every keyframe.
+    Avc,
+    /// Annex B start codes.
+    ByteStream,
+}
+
+impl H264Format {
+    /// `stream-format` in the caps.
+    pub(crate) fn stream_format(self) -> &'static str {
+        match self {
+            H264Format::Avc => "avc",
+            H264Format::ByteStream => "byte-stream",
+        }
+    }
+}
+
+/// Clock of [PlayerOptions::clock].
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+pub enum PipelineClock {
+    /// The system's realtime clock, for hosts that are NTP-synced themselves.
+    System,
+    /// An NTP server's time, [PlayerOptions::ntp_server].
+    Ntp,
+}
+
+impl PipelineClock {
+    /// NTP time in ns of this clock's zero.
+    fn epoch(self) -> i128 {
+        match self {
+            // The Unix epoch
+            PipelineClock::System => 2_208_988_800 * 1_000_000_000,
+            // NtpClock keeps NTP time, like rtpbin's ntp-time-source=clock-time expects
+            PipelineClock::Ntp => 0,
+        }
+    }
+}
+
+/// How much appsrc queues by default before the packet loop stops reading, e.g. while
+/// a slow decoder catches up.
+const APPSRC_MAX_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Video `encoding_name`s we can depay and decode.
+const VIDEO_ENCODINGS: &[&str] = &["h264", "h265", "vp8", "vp9", "jpeg", "av1"];
+
+/// Audio `encoding_name`s we can depay and decode.
+const AUDIO_ENCODINGS: &[&str] = &["mpeg4-generic", "pcmu", "pcma", "opus"];
+
+/// Options for [RtsPlayer::connect].
+///
+/// There's no keepalive option: Retina sends its own `GET_PARAMETER` (or `OPTIONS`)
+/// keepalives on a fixed interval, as long as the session is being read.
+///
+/// Nor is there two-way talk: an ONVIF camera only offers its backchannel to a DESCRIBE
+/// with `Require: www.onvif.org/ver20/backchannel`, which Retina can't send, and Retina
+/// only receives RTP, so there'd be no sending the microphone's audio on it either.
+///
+/// Nor a proxy: Retina opens the RTSP connection itself, with no way of handing it one
+/// through SOCKS. To reach a camera behind a bastion, forward a port to it instead,
+/// e.g. `ssh -L 8554:camera:554 bastion` and `rtsp://localhost:8554/...`, with TCP
+/// transport so the RTP comes through the same connection.
+pub struct PlayerOptions {
+    creds: Option<Credentials>,
+    user_agent: String,
+    transport: Option<TransportKind>,
+    ip_family: IpFamily,
+    initial_timestamp: InitialTimestamp,
+    ignore_zero_seq: bool,
+    enforce_timestamps: Option<NonZeroU32>,
+    lenient: bool,
+    codec: Codec,
+    video_stream: Option<usize>,
+    stream_control: Option<String>,
+    max_bitrate: Option<u32>,
+    record: Option<PathBuf>,
+    output_format: OutputFormat,
+    segment_duration: Option<Duration>,
+    rtmp: Option<url::Url>,
+    dump_es: Option<PathBuf>,
+    snapshot_interval: Option<Duration>,
+    snapshot_dir: PathBuf,
+    h264_format: H264Format,
+    snapshot: Option<PathBuf>,
+    save_sdp: Option<PathBuf>,
+    headless: bool,
+    verbose_caps: bool,
+    log_sender_reports: bool,
+    decoder: String,
+    hwaccel: Option<Hwaccel>,
+    video_sink: String,
+    window_title: Option<String>,
+    window_geometry: Option<WindowGeometry>,
+    audio: bool,
+    mute: bool,
+    audio_channels: NonZeroU16,
+    reconnect: bool,
+    recover_on_error: bool,
+    reconnect_max_delay: Duration,
+    startup_retries: u32,
+    startup_retry_delay: Duration,
+    stats_interval: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    describe_timeout: Option<Duration>,
+    setup_timeout: Option<Duration>,
+    play_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    stall_timeout: Option<Duration>,
+    teardown_timeout: Duration,
+    max_packets: Option<u64>,
+    session_group: std::sync::Arc<SessionGroup>,
+    jitter_latency: Option<Duration>,
+    queue_size: Option<Duration>,
+    pad_offset: Duration,
+    av_sync_offset: i64,
+    clock: Option<PipelineClock>,
+    ntp_server: String,
+    low_latency: bool,
+    branches: HashMap<String, String>,
+    map_pt: HashMap<u8, String>,
+    appsrc_min_latency: Option<Duration>,
+    appsrc_max_latency: Option<Duration>,
+    appsrc_max_bytes: u64,
+    do_timestamp: bool,
+    simulate_loss: f64,
+    simulate_reorder: f64,
+    seed: u64,
+    trace_timestamps: Option<u64>,
+    pipeline: bool,
+    count_buffers: bool,
+    strict: bool,
+    on_rtp_packet: Option<Box<dyn Fn(&retina::rtp::ReceivedPacket) + Send + Sync>>,
+    on_metadata: Option<Box<dyn Fn(&[u8]) + Send + Sync>>,
+    on_first_frame: Option<std::sync::Arc<dyn Fn() + Send + Sync>>,
+    name: Option<String>,
+}
+
+impl Default for PlayerOptions {
+    fn default() -> Self {
+        PlayerOptions {
+            creds: None,
+            user_agent: "Retina sdp example".to_owned(),
+            transport: None,
+            ip_family: IpFamily::Auto,
+            initial_timestamp: InitialTimestamp::Default,
+            ignore_zero_seq: false,
+            enforce_timestamps: None,
+            lenient: false,
+            codec: Codec::Auto,
+            video_stream: None,
+            stream_control: None,
+            max_bitrate: None,
+            record: None,
+            output_format: OutputFormat::Mp4,
+            segment_duration: None,
+            rtmp: None,
+            dump_es: None,
+            snapshot_interval: None,
+            snapshot_dir: PathBuf::from("."),
+            h264_format: H264Format::ByteStream,
+            snapshot: None,
+            save_sdp: None,
+            headless: false,
+            verbose_caps: false,
+            log_sender_reports: false,
+            decoder: "decodebin".to_owned(),
+            hwaccel: None,
+            video_sink: "autovideosink".to_owned(),
+            window_title: None,
+            window_geometry: None,
+            audio: true,
+            mute: false,
+            audio_channels: NonZeroU16::new(1).unwrap(),
+            reconnect: false,
+            recover_on_error: false,
+            reconnect_max_delay: Duration::from_secs(60),
+            startup_retries: 0,
+            startup_retry_delay: Duration::from_secs(5),
+            stats_interval: None,
+            connect_timeout: None,
+            describe_timeout: None,
+            setup_timeout: None,
+            play_timeout: None,
+            read_timeout: None,
+            stall_timeout: None,
+            teardown_timeout: Duration::from_secs(5),
+            max_packets: None,
+            session_group: Default::default(),
+            jitter_latency: None,
+            queue_size: None,
+            pad_offset: Duration::from_secs(1),
+            av_sync_offset: 0,
+            clock: None,
+            ntp_server: "pool.ntp.org:123".to_owned(),
+            low_latency: false,
+            branches: HashMap::new(),
+            map_pt: HashMap::new(),
+            appsrc_min_latency: None,
+            appsrc_max_latency: None,
+            appsrc_max_bytes: APPSRC_MAX_BYTES,
+            do_timestamp: false,
+            simulate_loss: 0.0,
+            simulate_reorder: 0.0,
+            seed: 0,
+            trace_timestamps: None,
+            pipeline: true,
+            count_buffers: false,
+            strict: false,
+            on_rtp_packet: None,
+            on_metadata: None,
+            on_first_frame: None,
+            name: None,
+        }
+    }
+}
+
+impl PlayerOptions {
+    /// Credentials to send if the server requires authentication.
+    ///
+    /// Retina answers the server's `WWW-Authenticate` challenge with the scheme it asks
+    /// for; there's no forcing basic or digest.
+    pub fn creds(mut self, creds: Option<Credentials>) -> Self {
+        self.creds = creds;
+        self
+    }
+
+    /// `User-Agent` to send with every request.
+    ///
+    /// Retina has no way of sending other extra headers.
+    pub fn user_agent(mut self, user_agent: String) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    /// RTP transport to request; Retina picks one by default.
+    pub fn transport(mut self, transport: Option<TransportKind>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Address family to connect over, e.g. for a camera whose IPv6 address is
+    /// unreachable. Retina has no option for this, so the URL's host gets resolved here
+    /// and replaced with an address of the family.
+    pub fn ip_family(mut self, ip_family: IpFamily) -> Self {
+        self.ip_family = ip_family;
+        self
+    }
+
+    /// What to do with the PLAY response's initial RTP timestamps.
+    pub fn initial_timestamp(mut self, initial_timestamp: InitialTimestamp) -> Self {
+        self.initial_timestamp = initial_timestamp;
+        self
+    }
+
+    /// Drop packets with sequence number 0 that some cameras send before the ones
+    /// `RTP-Info` announces, which otherwise show up as a jump in sequence numbers (and
+    /// lost packets) at the start.
+    pub fn ignore_zero_seq(mut self, ignore_zero_seq: bool) -> Self {
+        self.ignore_zero_seq = ignore_zero_seq;
+        self
+    }
+
+    /// Fail the session when a stream's timestamps go backwards or jump forward more
+    /// than this many seconds, instead of passing them on; off by default.
+    pub fn enforce_timestamps(mut self, max_jump_secs: Option<NonZeroU32>) -> Self {
+        self.enforce_timestamps = max_jump_secs;
+        self
+    }
+
+    /// Tolerates what Retina can: RTCP of unknown SSRCs is dropped, and so is interleaved
+    /// data on channels no stream was set up on. RTP packets with no payload are
+    /// skipped rather than pushed.
+    ///
+    /// Retina has no lenient RTP parsing, so a packet it can't parse still ends the
+    /// session; [PlayerOptions::reconnect] gets it back.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Video codec to prefer when the camera offers several.
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Index of the video stream to play, instead of picking one by codec.
+    pub fn video_stream(mut self, video_stream: Option<usize>) -> Self {
+        self.video_stream = video_stream;
+        self
+    }
+
+    /// Control URL (or its last part, e.g. `trackID=2`) of the video stream to play,
+    /// instead of picking one by codec.
+    pub fn stream_control(mut self, stream_control: Option<String>) -> Self {
+        self.stream_control = stream_control;
+        self
+    }
+
+    /// Plays the video stream of the highest bitrate up to this many kbps, by the SDP's
+    /// `b=AS` or `b=TIAS` lines, e.g. a substream over a metered link. Streams without
+    /// one aren't considered; if none qualify, it's picked by codec.
+    pub fn max_bitrate(mut self, max_bitrate: Option<u32>) -> Self {
+        self.max_bitrate = max_bitrate;
+        self
+    }
+
+    /// Records the video to this file while displaying it; only records it when
+    /// [PlayerOptions::headless]. The audio is recorded too, if the
+    /// [PlayerOptions::output_format] takes its codec: AAC and Opus always, G.711 only
+    /// in MKV. Sessions after a reconnect
+    /// record to their own file, e.g. `camera-1.mp4`, and so on, instead of truncating
+    /// the first's; likewise [PlayerOptions::dump_es].
+    pub fn record(mut self, record: Option<PathBuf>) -> Self {
+        self.record = record;
+        self
+    }
+
+    /// Container of [PlayerOptions::record]; MP4 by default.
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// Splits the recording into files about this long, starting each at a keyframe.
+    /// [PlayerOptions::record] is then a template of their names, e.g. `clip-%05d.mkv`.
+    pub fn segment_duration(mut self, segment_duration: Option<Duration>) -> Self {
+        self.segment_duration = segment_duration;
+        self
+    }
+
+    /// Pushes the video to this RTMP server instead of recording it, re-encoding it to
+    /// H.264 if it isn't already. Audio isn't sent.
+    pub fn rtmp(mut self, rtmp: Option<url::Url>) -> Self {
+        self.rtmp = rtmp;
+        self
+    }
+
+    /// Writes H.264 or H.265 video to this file as it comes, parsed but not decoded or
+    /// muxed: an elementary stream other tools can mux later, Annex B unless
+    /// [PlayerOptions::h264_format] says otherwise for H.264. Other codecs have no such
+    /// format, and don't get written.
+    pub fn dump_es(mut self, dump_es: Option<PathBuf>) -> Self {
+        self.dump_es = dump_es;
+        self
+    }
+
+    /// Stream format of the H.264 [PlayerOptions::dump_es] writes; Annex B by default.
+    pub fn h264_format(mut self, h264_format: H264Format) -> Self {
+        self.h264_format = h264_format;
+        self
+    }
+
+    /// Saves the first video keyframe to this JPEG file, then stops. Nothing gets
+    /// recorded meanwhile.
+    pub fn snapshot(mut self, snapshot: Option<PathBuf>) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// Saves a JPEG of the video this often while playing it, to
+    /// [PlayerOptions::snapshot_dir], named by the Unix time after the
+    /// [PlayerOptions::name] if any, e.g. `cam0-1700000000.000.jpg`. Decodes the video
+    /// to do so even when headless.
+    pub fn snapshot_interval(mut self, snapshot_interval: Option<Duration>) -> Self {
+        self.snapshot_interval = snapshot_interval;
+        self
+    }
+
+    /// Directory of [PlayerOptions::snapshot_interval]'s JPEGs, made if it doesn't
+    /// exist; the current one by default.
+    pub fn snapshot_dir(mut self, snapshot_dir: PathBuf) -> Self {
+        self.snapshot_dir = snapshot_dir;
+        self
+    }
+
+    /// Writes the SDP to this file on every DESCRIBE.
+    pub fn save_sdp(mut self, save_sdp: Option<PathBuf>) -> Self {
+        self.save_sdp = save_sdp;
+        self
+    }
+
+    /// Doesn't decode or display video, e.g. on a server without a display.
+    pub fn headless(mut self, headless: bool) -> Self {
+        self.headless = headless;
+        self
+    }
+
+    /// Logs the caps negotiated at every pad of the branches, e.g. to debug decoding.
+    pub fn verbose_caps(mut self, verbose_caps: bool) -> Self {
+        self.verbose_caps = verbose_caps;
+        self
+    }
+
+    /// Logs the RTP and NTP timestamps of each RTCP sender report.
+    ///
+    /// RTCP itself can't be turned off or tuned: Retina only receives it, with no options
+    /// for it, and sends none, not even receiver reports.
+    pub fn log_sender_reports(mut self, log_sender_reports: bool) -> Self {
+        self.log_sender_reports = log_sender_reports;
+        self
+    }
+
+    /// Element decoding video, e.g. `vaapidecodebin`; `decodebin` by default.
+    pub fn decoder(mut self, decoder: String) -> Self {
+        self.decoder = decoder;
+        self
+    }
+
+    /// Picks the video decoder for each codec, instead of [PlayerOptions::decoder].
+    /// Falls back to that when none of the chosen decoders is installed.
+    pub fn hwaccel(mut self, hwaccel: Option<Hwaccel>) -> Self {
+        self.hwaccel = hwaccel;
+        self
+    }
+
+    /// Element displaying video, e.g. `glimagesink`; `autovideosink` by default.
+    pub fn video_sink(mut self, video_sink: String) -> Self {
+        self.video_sink = video_sink;
+        self
+    }
+
+    /// Title of the video window, for sinks that take it from the stream's tags, e.g.
+    /// `ximagesink` and `xvimagesink`.
+    pub fn window_title(mut self, window_title: Option<String>) -> Self {
+        self.window_title = window_title;
+        self
+    }
+
+    /// Size and position of the video window, for sinks that are video overlays.
+    pub fn window_geometry(mut self, window_geometry: Option<WindowGeometry>) -> Self {
+        self.window_geometry = window_geometry;
+        self
+    }
+
+    /// Sets up and plays the audio stream, if the camera has one.
+    pub fn audio(mut self, audio: bool) -> Self {
+        self.audio = audio;
+        self
+    }
+
+    /// Sets up and decodes the audio stream, but sends it to a `fakesink` instead of the
+    /// speakers. It's still recorded.
+    pub fn mute(mut self, mute: bool) -> Self {
+        self.mute = mute;
+        self
+    }
+
+    /// Channels of audio whose SDP doesn't say, which per RFC 3551 means mono; 1 by
+    /// default. Some depayloaders don't negotiate without them in the caps.
+    pub fn audio_channels(mut self, audio_channels: NonZeroU16) -> Self {
+        self.audio_channels = audio_channels;
+        self
+    }
+
+    /// Reconnects instead of returning when the session fails or ends.
+    pub fn reconnect(mut self, reconnect: bool) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Restarts a branch whose element errors, e.g. a sink losing its display for a
+    /// moment, instead of failing the session. Errors elsewhere, in a branch feeding the
+    /// recording, or in a branch again within 10s of its restart still do.
+    pub fn recover_on_error(mut self, recover_on_error: bool) -> Self {
+        self.recover_on_error = recover_on_error;
+        self
+    }
+
+    /// Longest wait between reconnect attempts.
+    pub fn reconnect_max_delay(mut self, reconnect_max_delay: Duration) -> Self {
+        self.reconnect_max_delay = reconnect_max_delay;
+        self
+    }
+
+    /// How many more times [RtsPlayer::connect] tries when connecting fails, e.g. when
+    /// starting at boot before the network is up.
+    pub fn startup_retries(mut self, startup_retries: u32) -> Self {
+        self.startup_retries = startup_retries;
+        self
+    }
+
+    /// Wait between [PlayerOptions::startup_retries]; 5s by default.
+    pub fn startup_retry_delay(mut self, startup_retry_delay: Duration) -> Self {
+        self.startup_retry_delay = startup_retry_delay;
+        self
+    }
+
+    /// Logs packet counts and bitrates of each stream this often; never if zero.
+    pub fn stats_interval(mut self, stats_interval: Option<Duration>) -> Self {
+        self.stats_interval = stats_interval.filter(|interval| !interval.is_zero());
+        self
+    }
+
+    /// Fails DESCRIBE, SETUP or PLAY requests taking longer than this.
+    pub fn connect_timeout(mut self, connect_timeout: Option<Duration>) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Timeout of DESCRIBE, instead of [PlayerOptions::connect_timeout].
+    pub fn describe_timeout(mut self, describe_timeout: Option<Duration>) -> Self {
+        self.describe_timeout = describe_timeout;
+        self
+    }
+
+    /// Timeout of each SETUP, instead of [PlayerOptions::connect_timeout].
+    pub fn setup_timeout(mut self, setup_timeout: Option<Duration>) -> Self {
+        self.setup_timeout = setup_timeout;
+        self
+    }
+
+    /// Timeout of PLAY, instead of [PlayerOptions::connect_timeout], e.g. for cameras
+    /// slow to start streaming.
+    pub fn play_timeout(mut self, play_timeout: Option<Duration>) -> Self {
+        self.play_timeout = play_timeout;
+        self
+    }
+
+    /// Fails the session when no packet arrives for this long.
+    pub fn read_timeout(mut self, read_timeout: Option<Duration>) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// Fails the session when no video frame reaches the sink for this long, so with
+    /// [PlayerOptions::reconnect] a hung decoder gets a new pipeline. The error tells
+    /// a decoding stall, with packets still arriving or appsrc full, from packets not
+    /// arriving. Never when headless, as nothing's displayed.
+    pub fn stall_timeout(mut self, stall_timeout: Option<Duration>) -> Self {
+        self.stall_timeout = stall_timeout;
+        self
+    }
+
+    /// Longest to wait for the server to acknowledge the TEARDOWN of the last session
+    /// when [RtsPlayer::run] returns, so cameras with a few sessions don't run out;
+    /// 5s by default.
+    pub fn teardown_timeout(mut self, teardown_timeout: Duration) -> Self {
+        self.teardown_timeout = teardown_timeout;
+        self
+    }
+
+    /// Stops each session after this many RTP packets, as [RtsPlayer::stop] would, e.g.
+    /// to capture a fixed sample.
+    pub fn max_packets(mut self, max_packets: Option<u64>) -> Self {
+        self.max_packets = max_packets;
+        self
+    }
+
+    /// Buffers each stream in an `rtpjitterbuffer` with this latency, reordering late
+    /// packets at the cost of delay. None by default.
+    pub fn jitter_latency(mut self, jitter_latency: Option<Duration>) -> Self {
+        self.jitter_latency = jitter_latency;
+        self
+    }
+
+    /// Most the queue before each sink holds, so a render stall doesn't hold up
+    /// receiving; the queue's default of 1s if not given.
+    pub fn queue_size(mut self, queue_size: Option<Duration>) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Shifts the timestamps of every stream this much later, so the sinks get buffers
+    /// in time despite the depaying and decoding before them. Lower it for less latency,
+    /// at the risk of late frames getting dropped; 1s by default.
+    pub fn pad_offset(mut self, pad_offset: Duration) -> Self {
+        self.pad_offset = pad_offset;
+        self
+    }
+
+    /// Nanoseconds to shift audio by relative to video, on top of
+    /// [PlayerOptions::pad_offset], for cameras with a constant lip-sync error: positive
+    /// plays audio later, negative earlier. Sinks that don't sync, as with
+    /// [PlayerOptions::low_latency], ignore it.
+    pub fn av_sync_offset(mut self, av_sync_offset: i64) -> Self {
+        self.av_sync_offset = av_sync_offset;
+        self
+    }
+
+    /// Wallclock the pipeline runs on instead of GStreamer's monotonic system clock.
+    /// Every pipeline on the same kind of clock gets the same base time, and streams are
+    /// mapped onto the clock by their sender reports, so several cameras play in sync,
+    /// e.g. for a multi-view; that needs the cameras' own clocks NTP-synced, and holds
+    /// from each stream's first sender report.
+    pub fn clock(mut self, clock: Option<PipelineClock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// `host:port` of the NTP server of [PipelineClock::Ntp]; `pool.ntp.org:123` by
+    /// default.
+    pub fn ntp_server(mut self, ntp_server: String) -> Self {
+        self.ntp_server = ntp_server;
+        self
+    }
+
+    /// Trades smoothness for latency: no jitter buffer or pad offset, sinks that render
+    /// buffers on arrival instead of by the clock, and queues before them that keep only
+    /// the latest buffer. Overrides the options for those.
+    pub fn low_latency(mut self, low_latency: bool) -> Self {
+        self.low_latency = low_latency;
+        self
+    }
+
+    /// Launch descriptions to play streams with instead of the built-in ones, by RTP
+    /// encoding-name, e.g. `H264` to `rtph264depay ! avdec_h264 ! autovideosink`.
+    pub fn branches(mut self, branches: HashMap<String, String>) -> Self {
+        self.branches = branches;
+        self
+    }
+
+    /// Encoding names to treat payload types as, instead of what the SDP says, for
+    /// cameras that get it wrong or use a name of their own; e.g. `96` to `H264`. The
+    /// stream is picked and its branch made by that name.
+    pub fn map_pt(mut self, map_pt: HashMap<u8, String>) -> Self {
+        self.map_pt = map_pt
+            .into_iter()
+            .map(|(pt, encoding_name)| (pt, encoding_name.to_lowercase()))
+            .collect();
+        self
+    }
+
+    /// Latency appsrc reports to the pipeline, which the sinks sync with; appsrc's
+    /// default if not given.
+    pub fn appsrc_min_latency(mut self, appsrc_min_latency: Option<Duration>) -> Self {
+        self.appsrc_min_latency = appsrc_min_latency;
+        self
+    }
+
+    /// Most latency appsrc reports to the pipeline; appsrc's default if not given.
+    pub fn appsrc_max_latency(mut self, appsrc_max_latency: Option<Duration>) -> Self {
+        self.appsrc_max_latency = appsrc_max_latency;
+        self
+    }
+
+    /// How much appsrc queues before the packet loop stops reading; 4 MiB by default.
+    pub fn appsrc_max_bytes(mut self, appsrc_max_bytes: u64) -> Self {
+        self.appsrc_max_bytes = appsrc_max_bytes;
+        self
+    }
+
+    /// Timestamps buffers with their arrival time, instead of from their RTP timestamps
+    /// and the sender reports.
+    pub fn do_timestamp(mut self, do_timestamp: bool) -> Self {
+        self.do_timestamp = do_timestamp;
+        self
+    }
+
+    /// For testing: drops this fraction of the RTP packets before the pipeline gets
+    /// them, as picked by [PlayerOptions::seed].
+    pub fn simulate_loss(mut self, simulate_loss: f64) -> Self {
+        self.simulate_loss = simulate_loss;
+        self
+    }
+
+    /// For testing: pushes this fraction of the RTP packets after their stream's next
+    /// one, as picked by [PlayerOptions::seed].
+    pub fn simulate_reorder(mut self, simulate_reorder: f64) -> Self {
+        self.simulate_reorder = simulate_reorder;
+        self
+    }
+
+    /// Seeds the packets [PlayerOptions::simulate_loss] and
+    /// [PlayerOptions::simulate_reorder] pick, so a test run can be repeated; 0 by
+    /// default.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Logs the RTP timestamp, PTS and arrival running time of every this many packets
+    /// of each stream, e.g. to see why audio and video are out of sync.
+    pub fn trace_timestamps(mut self, trace_timestamps: Option<u64>) -> Self {
+        self.trace_timestamps = trace_timestamps.filter(|&every| every > 0);
+        self
+    }
+
+    /// Plays the streams through a GStreamer pipeline (default). Without one they're only
+    /// received, e.g. for [PlayerOptions::on_rtp_packet], and GStreamer isn't initialized.
+    pub fn pipeline(mut self, pipeline: bool) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    /// Counts the buffers through each branch, for [RtsPlayer::branch_stats], e.g. to
+    /// tell a stream that doesn't decode from one that doesn't arrive.
+    pub fn count_buffers(mut self, count_buffers: bool) -> Self {
+        self.count_buffers = count_buffers;
+        self
+    }
+
+    /// Fails instead of discarding audio and video streams of encodings there's no
+    /// branch for; otherwise each gets a warning.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Calls `f` with every RTP packet, before it goes into the pipeline.
+    pub fn on_rtp_packet(
+        mut self,
+        f: impl Fn(&retina::rtp::ReceivedPacket) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_rtp_packet = Some(Box::new(f));
+        self
+    }
+
+    /// Sets up the first `application` stream too, e.g. of ONVIF analytics or KLV, and
+    /// calls `f` with each of its messages, put together from RTP packets up to the one
+    /// with the marker bit.
+    pub fn on_metadata(mut self, f: impl Fn(&[u8]) + Send + Sync + 'static) -> Self {
+        self.on_metadata = Some(Box::new(f));
+        self
+    }
+
+    /// Calls `f` once a session's video is shown, e.g. to hide a loading spinner: when
+    /// its first decoded frame goes to the video sink, which may hold it up to the pad
+    /// offset before rendering. Again for each new session or video payload type, and
+    /// never when headless.
+    pub fn on_first_frame(mut self, f: impl Fn() + Send + Sync + 'static) -> Self {
+        self.on_first_frame = Some(std::sync::Arc::new(f));
+        self
+    }
+
+    /// Names the pipeline, and prefixes the names of its elements, e.g. to tell cameras
+    /// apart when playing several.
+    pub fn name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+}
+
+/// An RTSP session played through a GStreamer pipeline.
+pub struct RtsPlayer {
+    url: url::Url,
+    opts: PlayerOptions,
+
+    /// The session [RtsPlayer::connect] set up, until [RtsPlayer::run] plays it.
+    prepared: std::sync::Mutex<Option<Prepared>>,
+
+    stats: Stats,
+    branches: BranchCounters,
+    /// The pipeline's current state; `Null` between sessions.
+    state: std::sync::Mutex<gst::State>,
+    reconnects: AtomicU64,
+
+    stop_tx: UnboundedSender<()>,
+    stop_rx: tokio::sync::Mutex<UnboundedReceiver<()>>,
+}
+
+/// A session that's described and set up, but not playing yet.
+struct Prepared {
+    session: Session<Described>,
+    fmtp: HashMap<u8, Vec<(String, String)>>,
+    /// When DESCRIBE was sent.
+    described: Instant,
+}
+
+/// How a session ended, if not with an error.
+#[derive(Debug)]
+enum Exit {
+    /// By [RtsPlayer::stop].
+    Stopped,
+    /// By the server.
+    Ended,
+}
+
+impl RtsPlayer {
+    /// Describes the session at `url` and sets up the streams to play.
+    pub async fn connect(url: url::Url, opts: PlayerOptions) -> Result<Self> {
+        if opts.pipeline {
+            gst::init()?;
+        }
+
+        let span = tracing::info_span!("rtsp", url = %log_url(&url));
+        span.in_scope(|| tracing::info!("Connecting to {}", log_url(&url)));
+
+        let mut retries = opts.startup_retries;
+        let prepared = loop {
+            match prepare(&url, &opts).instrument(span.clone()).await {
+                Ok(prepared) => break prepared,
+                Err(err) if retries > 0 => {
+                    retries -= 1;
+
+                    tracing::warn!(
+                        "Connecting failed, retrying in {:?} ({} left): {:#}",
+                        opts.startup_retry_delay,
+                        retries,
+                        err
+                    );
+                    tokio::time::sleep(opts.startup_retry_delay).await;
+                }
+                Err(err) => return Err(err),
+            }
+        };
+
+        let (stop_tx, stop_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        Ok(RtsPlayer {
+            url,
+            opts,
+            prepared: std::sync::Mutex::new(Some(prepared)),
+            stats: Stats::default(),
+            branches: BranchCounters::default(),
+            state: std::sync::Mutex::new(gst::State::Null),
+            reconnects: AtomicU64::new(0),
+            stop_tx,
+            stop_rx: tokio::sync::Mutex::new(stop_rx),
+        })
+    }
+
+    /// Plays until stopped, or until the session fails or ends and reconnecting is off.
+    pub async fn run(&self) -> Result<()> {
+        let mut stop = self.stop_rx.lock().await;
+        let sessions = self
+            .run_sessions(&mut stop)
+            .instrument(tracing::info_span!("rtsp", url = %log_url(&self.url)));
+
+        let res = match self.opts.stats_interval {
+            Some(interval) => tokio::select! {
+                res = sessions => res,
+                _ = stats::log_every(self.stats.clone(), interval) => unreachable!(),
+            },
+            None => sessions.await,
+        };
+
+        // Retina tears sessions down in the background once they're dropped
+        let teardown = self.opts.session_group.await_teardown();
+        match tokio::time::timeout(self.opts.teardown_timeout, teardown).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::warn!("TEARDOWN failed: {}", err),
+            Err(_) => tracing::warn!(
+                "TEARDOWN not done after {:?}; the server may keep the session until it times out",
+                self.opts.teardown_timeout
+            ),
+        }
+
+        res
+    }
+
+    /// Stops [RtsPlayer::run]. The first call sends EOS and waits for it to get through
+    /// the pipeline, so a recording gets finalized; a second call doesn't wait.
+    pub fn stop(&self) {
+        let _ = self.stop_tx.send(());
+    }
+
+    /// What's been received on each stream so far, by stream index.
+    pub fn stats(&self) -> BTreeMap<usize, StreamStats> {
+        self.stats.snapshot()
+    }
+
+    /// The buffers through each branch so far, by payload type, with
+    /// [PlayerOptions::count_buffers].
+    pub fn branch_stats(&self) -> BTreeMap<u32, BranchStats> {
+        self.branches.snapshot()
+    }
+
+    /// The current state of the pipeline; `Null` while not playing a session.
+    pub fn state(&self) -> gst::State {
+        *self.state.lock().unwrap()
+    }
+
+    /// Whether the streams are played through a pipeline, see [PlayerOptions::pipeline].
+    pub fn has_pipeline(&self) -> bool {
+        self.opts.pipeline
+    }
+
+    /// How many times the session was reconnected.
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// Plays the prepared session, then new ones for as long as reconnecting.
+    async fn run_sessions(&self, stop: &mut UnboundedReceiver<()>) -> Result<()> {
+        let mut prepared = self.prepared.lock().unwrap().take();
+
+        let mut delay = Duration::from_secs(1);
+        let max_delay = self.opts.reconnect_max_delay;
+
+        loop {
+            let started = Instant::now();
+
+            let res = match prepared.take() {
+                Some(prepared) => Ok(prepared),
+                None => prepare(&self.url, &self.opts).await,
+            };
+
+            let res = match res {
+                Ok(prepared) if self.opts.pipeline => {
+                    let branches = self.opts.count_buffers.then(|| &self.branches);
+                    play(
+                        prepared,
+                        &self.opts,
+                        self.reconnects(),
+                        &self.stats,
+                        branches,
+                        &self.state,
+                        stop,
+                    )
+                    .await
+                }
+                Ok(prepared) => receive(prepared, &self.opts, &self.stats, stop).await,
+                Err(err) => Err(err),
+            };
+
+            tracing::info!(
+                exit = ?res.as_ref().ok(),
+                uptime_secs = started.elapsed().as_secs(),
+                "Session torn down"
+            );
+
+            match res {
+                Ok(Exit::Stopped) => break,
+                Ok(Exit::Ended) if self.opts.snapshot.is_some() => break,
+                Ok(Exit::Ended) if self.opts.reconnect => tracing::warn!("Stream ended"),
+                Ok(Exit::Ended) => break,
+                Err(err) if self.opts.reconnect => tracing::warn!("Session failed: {:#}", err),
+                Err(err) => return Err(err),
+            }
+
+            // Start the backoff over once a session stayed up for a while
+            if started.elapsed() > max_delay {
+                delay = Duration::from_secs(1);
+            }
+
+            tracing::info!("Reconnecting in {:?}", delay);
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                Some(()) = stop.recv() => break,
+            }
+
+            delay = (delay * 2).min(max_delay);
+            self.reconnects.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that GStreamer has the elements playing with `opts` needs, failing with a list
+/// of the missing ones.
+pub fn check_elements(opts: &PlayerOptions) -> Result<()> {
+    gst::init()?;
+
+    let encoding_name = opts.codec.encoding_name().map(str::to_uppercase);
+    let missing = missing_elements(branch::elements(
+        &branch_options(opts),
+        encoding_name.as_deref(),
+    ));
+
+    if !missing.is_empty() {
+        bail!(
+            "Missing GStreamer elements: {}; install the plugins providing them",
+            missing.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// The elements missing from GStreamer for each encoding this plays, by RTP
+/// encoding-name.
+pub fn missing_encoding_elements() -> Result<Vec<(&'static str, Vec<String>)>> {
+    gst::init()?;
+
+    Ok(branch::ENCODING_ELEMENTS
+        .iter()
+        .map(|(encoding_name, elements)| {
+            let elements = elements.iter().map(|e| e.to_string()).collect();
+            (*encoding_name, missing_elements(elements))
+        })
+        .collect())
+}
+
+/// Whether GStreamer has the elements of `encoding_name`'s branch, besides the decoder.
+fn depayable(encoding_name: &str) -> bool {
+    branch::ENCODING_ELEMENTS
+        .iter()
+        .filter(|(e, _)| *e == encoding_name)
+        .all(|(_, elements)| {
+            missing_elements(elements.iter().map(|e| e.to_string()).collect()).is_empty()
+        })
+}
+
+/// Those of `elements` GStreamer has no factory for.
+fn missing_elements(mut elements: Vec<String>) -> Vec<String> {
+    elements.dedup();
+    elements.retain(|e| gst::ElementFactory::find(e).is_none());
+
+    elements
+}
+
+/// Describes the session at `url` and returns its SDP, without setting anything up.
+pub async fn describe(url: &url::Url, opts: &PlayerOptions) -> Result<Vec<u8>> {
+    let session = describe_session(url, opts).await?;
+
+    Ok(session.sdp().to_vec())
+}
+
+/// Describes the session at `url` and returns its streams as a JSON array, without
+/// setting anything up.
+pub async fn describe_streams_json(url: &url::Url, opts: &PlayerOptions) -> Result<String> {
+    let session = describe_session(url, opts).await?;
+
+    let streams: Vec<_> = session
+        .streams()
+        .iter()
+        .map(|s| {
+            format!(
+                "{{\"media\":{},\"encoding_name\":{},\"rtp_payload_type\":{},\"clock_rate\":{},\"channels\":{},\"control\":{}}}",
+                json_string(&s.media),
+                json_string(&s.encoding_name),
+                s.rtp_payload_type,
+                s.clock_rate,
+                s.channels.map_or("null".to_owned(), |c| c.to_string()),
+                s.control.as_ref().map_or("null".to_owned(), |c| json_string(c.as_str())),
+            )
+        })
+        .collect();
+
+    Ok(format!("[{}]", streams.join(",")))
+}
+
+/// `url` without its password, for logging.
+fn log_url(url: &url::Url) -> url::Url {
+    let mut url = url.clone();
+    let _ = url.set_password(None);
+    url
+}
+
+/// `s` as a quoted JSON string.
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+    json
+}
+
+/// DESCRIBEs `url`, logging and saving the SDP.
+async fn describe_session(url: &url::Url, opts: &PlayerOptions) -> Result<Session<Described>> {
+    // Retina would fail on these with an opaque error
+    if url.scheme() == "rtsps" {
+        bail!("rtsps:// isn't supported: Retina has no RTSP over TLS transport yet");
+    }
+
+    let started = Instant::now();
+
+    let url = timeout(
+        opts.connect_timeout,
+        "Resolving",
+        resolve(url, opts.ip_family),
+    )
+    .await?;
+
+    let mut options = retina::client::SessionOptions::default()
+        .session_group(opts.session_group.clone())
+        .creds(opts.creds.clone())
+        .user_agent(opts.user_agent.clone());
+
+    if opts.lenient {
+        options = options.unassigned_channel_data(UnassignedChannelDataPolicy::Ignore);
+    }
+
+    let session = timeout(
+        opts.describe_timeout.or(opts.connect_timeout),
+        "DESCRIBE",
+        retina::client::Session::describe(url, options),
+    )
+    .await?;
+
+    tracing::info!(
+        streams = session.streams().len(),
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "Described"
+    );
+    tracing::info!("SDP:\n{}\n\n", std::str::from_utf8(session.sdp())?);
+
+    if let Some(path) = &opts.save_sdp {
+        std::fs::write(path, session.sdp())?;
+    }
+
+    if let Some(group) = multicast_group(session.sdp()) {
+        tracing::warn!(
+            "SDP advertises multicast group {}, but Retina only receives unicast; requesting that",
+            group
+        );
+    }
+
+    Ok(session)
+}
+
+/// Describes the session and sets up the streams to play.
+async fn prepare(url: &url::Url, opts: &PlayerOptions) -> Result<Prepared> {
+    let described = Instant::now();
+    let mut session = describe_session(url, opts).await?;
+
+    let fmtp = fmtp(session.sdp());
+
+    // Make audio and video streams
+    {
+        // Make video stream
+        let video_stream_i = {
+            let streams = session.streams();
+
+            // Few have the AV1 plugins, so without them pick another stream
+            let av1 = !opts.pipeline || depayable("AV1");
+            if !av1 && streams.iter().any(|s| encoding_name(s, opts) == "av1") {
+                tracing::warn!("Not playing AV1: its depayloader or parser isn't installed");
+            }
+
+            let supported = |s: &Stream| {
+                s.media == "video"
+                    && VIDEO_ENCODINGS.contains(&encoding_name(s, opts))
+                    && (av1 || encoding_name(s, opts) != "av1")
+            };
+
+            for (i, s) in streams.iter().enumerate() {
+                tracing::info!(
+                    "Stream {}: media={} encoding={} control={:?}",
+                    i,
+                    s.media,
+                    s.encoding_name,
+                    s.control.as_ref().map(url::Url::as_str)
+                );
+            }
+
+            // There's no branch for these, so they don't get set up at all
+            for (i, s) in streams.iter().enumerate() {
+                let unsupported = match s.media.as_str() {
+                    "video" => !supported(s),
+                    "audio" => opts.audio && !AUDIO_ENCODINGS.contains(&encoding_name(s, opts)),
+                    _ => false,
+                };
+
+                if unsupported && opts.strict {
+                    bail!(
+                        "{} stream {} is {}, which isn't supported",
+                        s.media,
+                        i,
+                        encoding_name(s, opts)
+                    );
+                } else if unsupported {
+                    tracing::warn!(
+                        "Not playing {} stream {}: {} isn't supported",
+                        s.media,
+                        i,
+                        encoding_name(s, opts)
+                    );
+                }
+            }
+
+            let i = if let Some(i) = opts.video_stream {
+                match streams.get(i) {
+                    Some(s) if s.media == "video" => Some(i),
+                    _ => bail!("Stream {} isn't a video stream", i),
+                }
+            } else if let Some(control) = &opts.stream_control {
+                let i = streams.iter().position(|s| {
+                    s.media == "video"
+                        && s.control
+                            .as_ref()
+                            .map_or(false, |c| c.as_str().ends_with(control.as_str()))
+                });
+
+                match i {
+                    Some(i) => Some(i),
+                    None => bail!("No video stream with control URL {:?}", control),
+                }
+            } else {
+                let by_codec = || match opts.codec.encoding_name() {
+                    Some(preferred) => streams
+                        .iter()
+                        .position(|s| supported(s) && encoding_name(s, opts) == preferred)
+                        .or_else(|| streams.iter().position(supported)),
+                    None => streams.iter().position(supported),
+                };
+
+                match opts.max_bitrate {
+                    Some(max) => {
+                        let bitrates = bitrates(session.sdp());
+                        let under = streams
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, s)| supported(s))
+                            .filter_map(|(i, _)| Some((i, bitrates.get(i).copied()??)))
+                            .filter(|&(_, kbps)| kbps <= max)
+                            .max_by_key(|&(_, kbps)| kbps);
+
+                        match under {
+                            Some((i, kbps)) => {
+                                tracing::info!(
+                                    "Stream {} is {} kbps, the most up to {}",
+                                    i,
+                                    kbps,
+                                    max
+                                );
+                                Some(i)
+                            }
+                            None => {
+                                tracing::warn!(
+                                    "No video stream advertises a bitrate up to {} kbps; picking by codec",
+                                    max
+                                );
+                                by_codec()
+                            }
+                        }
+                    }
+                    None => by_codec(),
+                }
+            };
+
+            if let Some(i) = i {
+                tracing::info!(
+                    "Using {} video stream {}",
+                    encoding_name(&streams[i], opts),
+                    i
+                );
+
+                if encoding_name(&streams[i], opts) == "h264" {
+                    log_parameter_sets(fmtp.get(&streams[i].rtp_payload_type));
+                }
+
+                if opts.dump_es.is_some()
+                    && !["h264", "h265"].contains(&encoding_name(&streams[i], opts))
+                {
+                    tracing::warn!(
+                        "Not dumping the {} video: only H.264 and H.265 have an elementary stream format",
+                        encoding_name(&streams[i], opts)
+                    );
+                }
+            }
+
+            i
+        };
+
+        let video_stream_i = try_setup(&mut session, video_stream_i, opts).await;
+
+        // Make audio stream
+        // A snapshot is done when the video branch is, so only set that up
+        let audio_stream_i = if opts.audio && opts.snapshot.is_none() {
+            session.streams().iter().position(|s| {
+                if s.media == "audio" && AUDIO_ENCODINGS.contains(&encoding_name(s, opts)) {
+                    tracing::info!("Using {} audio stream", encoding_name(s, opts));
+                    return true;
+                }
+
+                false
+            })
+        } else {
+            None
+        };
+
+        let audio_stream_i = try_setup(&mut session, audio_stream_i, opts).await;
+
+        // Make metadata stream
+        let metadata_stream_i = if opts.on_metadata.is_some() && opts.snapshot.is_none() {
+            session
+                .streams()
+                .iter()
+                .position(|s| s.media == "application")
+        } else {
+            None
+        };
+
+        if let Some(i) = metadata_stream_i {
+            tracing::info!(
+                "Using {} metadata stream",
+                &session.streams()[i].encoding_name
+            );
+        }
+        let metadata_stream_i = try_setup(&mut session, metadata_stream_i, opts).await;
+
+        if video_stream_i.is_none() && audio_stream_i.is_none() && metadata_stream_i.is_none() {
+            bail!("Exiting because no video or audio stream was selected or could be set up; see log messages above");
+        }
+    }
+
+    Ok(Prepared {
+        session,
+        fmtp,
+        described,
+    })
+}
+
+/// Sets up stream `i` of `session` if given, returning it if that worked; a camera
+/// failing one stream, e.g. audio it doesn't really have, still gets the others played.
+async fn try_setup(
+    session: &mut Session<Described>,
+    i: Option<usize>,
+    opts: &PlayerOptions,
+) -> Option<usize> {
+    let i = i?;
+
+    match setup(session, i, opts).await {
+        Ok(()) => Some(i),
+        Err(err) => {
+            tracing::warn!("Playing without stream {}, SETUP failed: {:#}", i, err);
+            None
+        }
+    }
+}
+
+/// Sets up stream `i` of `session`.
+async fn setup(session: &mut Session<Described>, i: usize, opts: &PlayerOptions) -> Result<()> {
+    let started = Instant::now();
+
+    let setup = session.setup(i, setup_options(opts.transport));
+    timeout(opts.setup_timeout.or(opts.connect_timeout), "SETUP", setup).await?;
+
+    // It keeps the SSRC to itself
+    let s = &session.streams()[i];
+    let transport = SetupTransport::parse(&format!("{:?}", s.ctx()));
+    tracing::info!(
+        stream = i,
+        media = %s.media,
+        encoding = %s.encoding_name,
+        pt = s.rtp_payload_type,
+        protocol = transport.protocol,
+        interleaved = transport.interleaved,
+        client_port = transport.client_port,
+        server_port = transport.server_port,
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "Set up"
+    );
+
+    Ok(())
+}
+
+/// What Retina negotiated for a stream: the RTP interleaved channel for TCP, the local
+/// and peer RTP ports for UDP, RTCP's being the next one. Its context keeps them to
+/// itself but for `Debug`, so that's where they're picked out of.
+#[derive(Debug)]
+struct SetupTransport {
+    protocol: &'static str,
+    interleaved: Option<u16>,
+    client_port: Option<u16>,
+    server_port: Option<u16>,
+}
+
+impl SetupTransport {
+    fn parse(ctx: &str) -> Self {
+        let field = |name: &str| {
+            let start = ctx.find(&format!("{}: ", name))? + name.len() + 2;
+            let digits = ctx[start..].split(|c: char| !c.is_ascii_digit()).next()?;
+            digits.parse().ok()
+        };
+
+        let protocol = if ctx.contains("Tcp") {
+            "TCP"
+        } else if ctx.contains("Udp") {
+            "UDP"
+        } else {
+            "unknown"
+        };
+
+        SetupTransport {
+            protocol,
+            interleaved: field("rtp_channel_id"),
+            client_port: field("local_rtp_port"),
+            server_port: field("peer_rtp_port"),
+        }
+    }
+}
+
+/// Logs the first packet of each stream in a session, `seen` being those that had one.
+fn log_first_packet(
+    seen: &mut HashSet<usize>,
+    rtp: &retina::rtp::ReceivedPacket,
+    described: Instant,
+) {
+    if seen.insert(rtp.stream_id()) {
+        tracing::info!(
+            stream = rtp.stream_id(),
+            pt = rtp.payload_type(),
+            since_describe_ms = described.elapsed().as_millis() as u64,
+            "First packet"
+        );
+    }
+}
+
+/// Puts together the messages of a metadata stream for [PlayerOptions::on_metadata].
+#[derive(Default)]
+struct Metadata(Vec<u8>);
+
+impl Metadata {
+    fn push(&mut self, rtp: &retina::rtp::ReceivedPacket, opts: &PlayerOptions) {
+        self.0.extend_from_slice(rtp.payload());
+
+        if rtp.mark() {
+            if let Some(f) = &opts.on_metadata {
+                f(&self.0);
+            }
+
+            self.0.clear();
+        }
+    }
+}
+
+/// Logs how `rtp` of `stream_id` got stamped `pts`, and when `appsrc` got it.
+fn trace_timestamps(
+    stream_id: usize,
+    rtp: &retina::rtp::ReceivedPacket,
+    pts: Option<gst::ClockTime>,
+    appsrc: &impl IsA<gst::Element>,
+) {
+    tracing::info!(
+        stream = stream_id,
+        rtp_timestamp = %rtp.timestamp(),
+        pts = %pts.display(),
+        arrival = %appsrc.current_running_time().display(),
+        "Timestamps"
+    );
+}
+
+/// Logs a sender report if asked to.
+fn log_sender_report(
+    opts: &PlayerOptions,
+    stream_id: usize,
+    rtp_timestamp: retina::Timestamp,
+    ntp_timestamp: retina::NtpTimestamp,
+) {
+    if opts.log_sender_reports {
+        tracing::info!(
+            stream = stream_id,
+            rtp_timestamp = %rtp_timestamp,
+            ntp_timestamp = %ntp_timestamp,
+            "Sender report"
+        );
+    }
+}
+
+/// Plays a set up session until it ends.
+async fn play(
+    prepared: Prepared,
+    opts: &PlayerOptions,
+    session_i: u64,
+    stats: &Stats,
+    branch_counters: Option<&BranchCounters>,
+    state: &std::sync::Mutex<gst::State>,
+    stop: &mut UnboundedReceiver<()>,
+) -> Result<Exit> {
+    let Prepared {
+        session,
+        fmtp,
+        described,
+    } = prepared;
+    let sdp = session.sdp().to_vec();
+
+    let pipeline = gst::Pipeline::new(opts.name.as_deref());
+
+    let clock_epoch = match opts.clock {
+        Some(kind) => {
+            let clock = pipeline_clock(kind, &opts.ntp_server).await?;
+
+            pipeline.use_clock(Some(&clock));
+            pipeline.set_start_time(gst::ClockTime::NONE);
+            pipeline.set_base_time(shared_base_time(kind, &clock)?);
+
+            Some(kind.epoch())
+        }
+        None => None,
+    };
+
+    add_recording_muxer(&pipeline, opts, session_i)?;
+
+    let appsrc = {
+        let appsrc = gst::ElementFactory::make("appsrc", Some(&element_name(opts, "rtssrc")))?;
+
+        {
+            let appsrc = appsrc.clone().dynamic_cast::<gst_app::AppSrc>().unwrap();
+
+            appsrc.set_stream_type(gst_app::AppStreamType::Stream);
+            appsrc.set_is_live(true);
+            appsrc.set_format(gst::Format::Time);
+            appsrc.set_do_timestamp(true);
+            appsrc.set_max_bytes(opts.appsrc_max_bytes);
+
+            if let Some(latency) = opts.appsrc_min_latency {
+                appsrc.set_property("min-latency", latency.as_nanos() as i64);
+            }
+            if let Some(latency) = opts.appsrc_max_latency {
+                appsrc.set_property("max-latency", latency.as_nanos() as i64);
+            }
+
+            appsrc.set_caps(Some(&gst::Caps::builder("application/x-rtp").build()));
+        }
+
+        appsrc
+    };
+
+    // When the displayed video's latest frame reached the sink, once there's one
+    let last_frame = std::sync::Arc::new(std::sync::Mutex::new(None::<Instant>));
+
+    let rtpptdemux = {
+        let rtpptdemux =
+            gst::ElementFactory::make("rtpptdemux", Some(&element_name(opts, "rtpptdemux")))?;
+
+        let mut branch = branch_options(opts);
+        branch.dump_es = branch.dump_es.map(|path| session_path(&path, session_i));
+        let dot_name = element_name(opts, "branch-added");
+        let branch_counters = branch_counters.cloned();
+        let strict = opts.strict;
+        let pad_offset = if opts.low_latency {
+            0
+        } else {
+            opts.pad_offset.as_nanos() as i64
+        };
+        let av_sync_offset = opts.av_sync_offset;
+        let on_first_frame = opts.on_first_frame.clone();
+        let stall_timeout = opts.stall_timeout;
+        let frames = last_frame.clone();
+
+        // Branch bin and media of each payload type
+        let branches = std::sync::Mutex::new(HashMap::<u32, (String, gst::Bin)>::new());
+
+        let pipeline_weak = pipeline.downgrade();
+        rtpptdemux.connect("new-payload-type", false, move |args| {
+            // A panic here would abort, so log what's wrong and leave the pad unlinked
+            let pt = args.get(1).and_then(|pt| pt.get::<u32>().ok());
+            let pad = args.get(2).and_then(|pad| pad.get::<gst::Pad>().ok());
+
+            let (pt, pad) = match (pt, pad) {
+                (Some(pt), Some(pad)) => (pt, pad),
+                _ => {
+                    tracing::error!("rtpptdemux: new-payload-type without a pt and pad");
+                    return None;
+                }
+            };
+
+            let caps = match pad.caps() {
+                Some(caps) => caps,
+                None => {
+                    tracing::error!("rtpptdemux: new pt={} without caps", pt);
+                    return None;
+                }
+            };
+            tracing::info!("rtpptdemux: new pt={}, caps={:?}", pt, caps);
+
+            let s = match caps.structure(0) {
+                Some(s) => s,
+                None => {
+                    tracing::error!("rtpptdemux: new pt={} with empty caps", pt);
+                    return None;
+                }
+            };
+
+            let encoding_name = s.get::<&str>("encoding-name").ok();
+            tracing::info!("encoding-name: {:?}", encoding_name);
+
+            match s.get::<&str>("media") {
+                Ok("audio") => pad.set_offset(pad_offset + av_sync_offset),
+                _ => pad.set_offset(pad_offset),
+            }
+
+            let launches = branch::launches(s, &branch);
+
+            if let Some(pipeline) = pipeline_weak.upgrade() {
+                if !branch::supported(encoding_name.unwrap_or_default(), &branch) {
+                    tracing::warn!(
+                        "Discarding pt={}: there's no branch for encoding-name {:?}",
+                        pt,
+                        encoding_name
+                    );
+
+                    if strict {
+                        gst::element_error!(
+                            pipeline,
+                            gst::StreamError::CodecNotFound,
+                            ("Unsupported encoding-name {:?} of pt={}", encoding_name, pt)
+                        );
+                        return None;
+                    }
+                }
+
+                let media = s.get::<&str>("media").unwrap_or_default();
+
+                // A camera switching payload types, e.g. on a resolution change, replaces
+                // the stream; don't leave its old branch playing
+                let mut branches = branches.lock().unwrap();
+                branches.retain(|old_pt, (old_media, old_bin)| {
+                    if *old_pt != pt && old_media != media {
+                        return true;
+                    }
+
+                    tracing::info!(
+                        "Removing the branch of pt={}, replaced by pt={}",
+                        old_pt,
+                        pt
+                    );
+                    remove_branch(&pipeline, old_bin);
+
+                    false
+                });
+
+                match add_branch(&pipeline, &pad, &launches, media, &branch) {
+                    Ok(bin) => {
+                        if let Some(counters) = &branch_counters {
+                            counters.count(&bin, pt, encoding_name.unwrap_or_default());
+                        }
+
+                        if media == "video" {
+                            branch::on_first_frame(&bin, on_first_frame.clone());
+
+                            if stall_timeout.is_some() {
+                                branch::watch_frames(&bin, frames.clone());
+                            }
+                        }
+
+                        branches.insert(pt, (media.to_owned(), bin));
+                    }
+                    Err(err) => tracing::error!("Can't add the branch of pt={}: {:#}", pt, err),
+                }
+
+                dump_dot(&pipeline, &dot_name);
+            }
+
+            None
+        });
+
+        rtpptdemux
+    };
+
+    {
+        pipeline.add_many(&[&appsrc, &rtpptdemux])?;
+        gst::Element::link_many(&[&appsrc, &rtpptdemux])?;
+    }
+
+    let appsrc = appsrc.clone().dynamic_cast::<gst_app::AppSrc>().unwrap();
+
+    // Whether appsrc's queue has room; packets aren't read while it's full. Neither are
+    // keepalives sent then, so a decoder stalled for long still loses the session
+    let (has_room_tx, mut has_room) = tokio::sync::watch::channel(true);
+    {
+        let has_room_tx = std::sync::Arc::new(has_room_tx);
+        let enough_data_tx = has_room_tx.clone();
+
+        appsrc.set_callbacks(
+            gst_app::AppSrcCallbacks::builder()
+                .need_data(move |_, _| {
+                    let _ = has_room_tx.send(true);
+                })
+                .enough_data(move |_| {
+                    let _ = enough_data_tx.send(false);
+                })
+                .build(),
+        );
+    }
+
+    // Retina always PLAYs from `Range: npt=0.000-`, so there's no asking an NVR for a
+    // recorded interval
+    let play = session.play(play_options(opts));
+    let mut session = timeout(opts.play_timeout.or(opts.connect_timeout), "PLAY", play).await?;
+    tracing::info!("Playing");
+    let mut bus_stream = pipeline.bus().unwrap().stream();
+
+    pipeline.set_state(gst::State::Playing)?;
+
+    // On stop send EOS and wait for it on the bus, so the recording gets finalized.
+    // A second stop doesn't wait.
+    let exit = async {
+        let mut eos = None;
+
+        // When each branch bin was last restarted by recover_on_error
+        let mut restarts = HashMap::<String, Instant>::new();
+
+        // RTP caps of each stream, and the stream whose caps appsrc has now
+        let mut caps = HashMap::new();
+        let mut caps_stream_id = None;
+
+        let mut timestamps = Timestamps::new(clock_epoch);
+        let mut framerates = FrameRates::new(&sdp);
+        let mut impair = Impair::new(opts.simulate_loss, opts.simulate_reorder, opts.seed);
+        let mut seen = HashSet::new();
+        let mut packets = 0;
+        let mut metadata = Metadata::default();
+        let mut traced = HashMap::<usize, u64>::new();
+
+        let read_timeout = tokio::time::sleep(opts.read_timeout.unwrap_or_default());
+        tokio::pin!(read_timeout);
+
+        let mut stall_check = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            let room = *has_room.borrow();
+
+            tokio::select! {
+                pkt = session.next(), if eos.is_none() && room => {
+                    if let Some(t) = opts.read_timeout {
+                        read_timeout.as_mut().reset(tokio::time::Instant::now() + t);
+                    }
+
+                    match pkt {
+                        Some(Ok(retina::client::PacketItem::RtpPacket(rtp))) => {
+                            let raw = rtp.raw();
+
+                            let stream_id = rtp.stream_id();
+                            stats.record(stream_id, &session.streams()[stream_id].media, raw);
+                            log_first_packet(&mut seen, &rtp, described);
+                            framerates.packet(stream_id, rtp.timestamp());
+
+                            // Nothing in the pipeline plays it
+                            if session.streams()[stream_id].media == "application" {
+                                if let Some(f) = &opts.on_rtp_packet {
+                                    f(&rtp);
+                                }
+
+                                metadata.push(&rtp, opts);
+                                continue;
+                            }
+
+                            if opts.lenient && rtp.payload().is_empty() {
+                                tracing::debug!(stream_id, "Skipping an RTP packet with no payload");
+                                continue;
+                            }
+
+                            let mut buffer = gst::Buffer::with_size(raw.len())?;
+
+                            {
+                                let buffer = buffer.get_mut().unwrap();
+
+                                buffer.copy_from_slice(0, raw).unwrap();
+
+                                // Until the pipeline has a clock, or with `do_timestamp`,
+                                // appsrc stamps the arrival time
+                                let pts = if opts.do_timestamp {
+                                    None
+                                } else {
+                                    timestamps.pts(stream_id, rtp.timestamp(), &appsrc)
+                                };
+
+                                if let Some(pts) = pts {
+                                    buffer.set_pts(pts);
+                                    buffer.set_dts(pts);
+                                }
+
+                                if let Some(every) = opts.trace_timestamps {
+                                    let n = traced.entry(stream_id).or_insert(0);
+                                    if *n % every == 0 {
+                                        trace_timestamps(stream_id, &rtp, pts, &appsrc);
+                                    }
+                                    *n += 1;
+                                }
+                            }
+
+                            if caps_stream_id != Some(stream_id) {
+                                let caps = caps.entry(stream_id).or_insert_with(|| {
+                                    let stream = &session.streams()[stream_id];
+                                    rtp_caps(stream, rtp.timestamp().clock_rate().get(), &fmtp, opts)
+                                });
+
+                                appsrc.set_caps(Some(&*caps));
+                                caps_stream_id = Some(stream_id);
+                            }
+
+                            if let Some(f) = &opts.on_rtp_packet {
+                                f(&rtp);
+                            }
+
+                            match &mut impair {
+                                Some(impair) => {
+                                    for buffer in impair.packet(stream_id, buffer) {
+                                        appsrc.push_buffer(buffer)?;
+                                    }
+                                }
+                                None => {
+                                    appsrc.push_buffer(buffer)?;
+                                }
+                            }
+
+                            packets += 1;
+                            if opts.max_packets == Some(packets) {
+                                tracing::info!("Got {} packets, stopping", packets);
+
+                                let _ = appsrc.end_of_stream()?;
+                                eos = Some(Exit::Stopped);
+                            }
+                        }
+                        Some(Err(err)) => return Err(err.into()),
+                        Some(Ok(retina::client::PacketItem::SenderReport(sr))) => {
+                            log_sender_report(opts, sr.stream_id, sr.rtp_timestamp, sr.ntp_timestamp);
+                            timestamps.sender_report(sr.stream_id, sr.rtp_timestamp, sr.ntp_timestamp);
+                        }
+                        None => {
+                            let _ = appsrc.end_of_stream()?;
+                            eos = Some(Exit::Ended);
+                        }
+                        Some(Ok(_)) => unreachable!(),
+                    }
+                }
+                _ = &mut read_timeout, if opts.read_timeout.is_some() && eos.is_none() && room => {
+                    bail!("No packets for {:?}", opts.read_timeout.unwrap());
+                }
+                _ = stall_check.tick(), if opts.stall_timeout.is_some() && eos.is_none() => {
+                    let stall_timeout = opts.stall_timeout.unwrap();
+                    let since = last_frame.lock().unwrap().map(|frame| frame.elapsed());
+
+                    if let Some(since) = since.filter(|&since| since > stall_timeout) {
+                        // A stalled decoder fills the queues up to appsrc's
+                        let packets = stats.snapshot().values().any(|s| {
+                            s.media == "video" && s.last_packet.map_or(false, |t| t.elapsed() < stall_timeout)
+                        });
+
+                        if packets || !room {
+                            bail!("Decoding stalled: no video frame for {:?}", since);
+                        }
+                        bail!("No video packets or frames for {:?}", since);
+                    }
+                }
+                Ok(()) = has_room.changed(), if !room => {
+                    tracing::trace!("appsrc has room again");
+
+                    // Not reading while it was full doesn't count
+                    if let Some(t) = opts.read_timeout {
+                        read_timeout.as_mut().reset(tokio::time::Instant::now() + t);
+                    }
+                }
+                Some(()) = stop.recv() => {
+                    if eos.is_some() {
+                        tracing::warn!("Stopped again, not waiting for EOS");
+                        break Ok(Exit::Stopped);
+                    }
+
+                    tracing::info!("Stopping, finishing the stream");
+
+                    let _ = appsrc.end_of_stream()?;
+                    eos = Some(Exit::Stopped);
+                }
+                msg = bus_stream.next() => {
+                    if let Some(msg) = msg {
+                        use gst::MessageView;
+
+                        match msg.view() {
+                            MessageView::Eos(_) => break Ok(eos.unwrap_or(Exit::Ended)),
+                            MessageView::Error(err) => {
+                                dump_dot(&pipeline, &element_name(opts, "error"));
+
+                                let bin = errored_branch(&pipeline, &msg).filter(|bin| {
+                                    let last = restarts.get(bin.name().as_str());
+                                    opts.recover_on_error
+                                        && last.map_or(true, |t| t.elapsed() > RECOVER_WINDOW)
+                                });
+                                let bin = match bin {
+                                    Some(bin) => bin,
+                                    None => bail!(err.error()),
+                                };
+
+                                tracing::warn!(
+                                    "Restarting {} after an error: {}{}",
+                                    bin.name(),
+                                    err.error(),
+                                    err.debug()
+                                        .map(|debug| format!(" ({})", debug))
+                                        .unwrap_or_default()
+                                );
+                                restart_branch(&bin)?;
+                                restarts.insert(bin.name().to_string(), Instant::now());
+                            }
+                            MessageView::StateChanged(change)
+                                if msg.src().as_ref() == Some(pipeline.upcast_ref()) =>
+                            {
+                                *state.lock().unwrap() = change.current();
+
+                                if change.current() == gst::State::Playing {
+                                    dump_dot(&pipeline, &element_name(opts, "playing"));
+                                }
+                            }
+                            _ => {},
+                        }
+                    } else {
+                        break Ok(eos.unwrap_or(Exit::Ended));
+                    }
+                }
+            }
+        }
+    }
+    .await;
+
+    // A stop already sent EOS; a failure leaves the recording unfinalized without one
+    if exit.is_err() {
+        if let Some(mux) = pipeline.by_name("recmux") {
+            finish_recording(&pipeline, &mux, &mut bus_stream, opts.teardown_timeout).await;
+        }
+    }
+
+    *state.lock().unwrap() = gst::State::Null;
+    pipeline.set_state(gst::State::Null)?;
+
+    exit
+}
+
+/// Sends EOS into `mux` and the rest of `pipeline`, and waits up to `timeout` for it to
+/// reach the sinks, so a failed session's recording is still playable. The muxer gets
+/// it directly, in case what failed keeps it from getting there.
+async fn finish_recording(
+    pipeline: &gst::Pipeline,
+    mux: &gst::Element,
+    bus_stream: &mut gst::bus::BusStream,
+    timeout: Duration,
+) {
+    tracing::info!("Finishing the recording");
+
+    for pad in mux.sink_pads() {
+        pad.send_event(gst::event::Eos::new());
+    }
+    pipeline.send_event(gst::event::Eos::new());
+
+    let eos = async {
+        while let Some(msg) = bus_stream.next().await {
+            if let gst::MessageView::Eos(_) = msg.view() {
+                break;
+            }
+        }
+    };
+
+    if tokio::time::timeout(timeout, eos).await.is_err() {
+        tracing::warn!("No EOS after {:?}; the recording may be cut short", timeout);
+    }
+}
+
+/// `path` for the `session_i`th session, counting from 0: `path` itself for the first,
+/// then e.g. `camera-1.mp4`, so a reconnect doesn't truncate what's been recorded.
+fn session_path(path: &Path, session_i: u64) -> PathBuf {
+    if session_i == 0 {
+        return path.to_owned();
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, session_i, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, session_i),
+    };
+
+    path.with_file_name(name)
+}
+
+/// Receives a set up session until it ends, without a pipeline.
+async fn receive(
+    prepared: Prepared,
+    opts: &PlayerOptions,
+    stats: &Stats,
+    stop: &mut UnboundedReceiver<()>,
+) -> Result<Exit> {
+    let described = prepared.described;
+    let mut framerates = FrameRates::new(prepared.session.sdp());
+    let play = prepared.session.play(play_options(opts));
+    let mut session = timeout(opts.play_timeout.or(opts.connect_timeout), "PLAY", play).await?;
+    tracing::info!("Playing");
+
+    let mut seen = HashSet::new();
+    let mut packets = 0;
+    let mut metadata = Metadata::default();
+
+    loop {
+        let next = async {
+            match opts.read_timeout {
+                Some(t) => tokio::time::timeout(t, session.next())
+                    .await
+                    .map_err(|_| eyre!("No packets for {:?}", t)),
+                None => Ok(session.next().await),
+            }
+        };
+
+        let pkt = tokio::select! {
+            pkt = next => pkt?,
+            Some(()) = stop.recv() => break Ok(Exit::Stopped),
+        };
+
+        match pkt {
+            Some(Ok(retina::client::PacketItem::RtpPacket(rtp))) => {
+                let stream_id = rtp.stream_id();
+                stats.record(stream_id, &session.streams()[stream_id].media, rtp.raw());
+                log_first_packet(&mut seen, &rtp, described);
+                framerates.packet(stream_id, rtp.timestamp());
+
+                if let Some(f) = &opts.on_rtp_packet {
+                    f(&rtp);
+                }
+
+                if session.streams()[stream_id].media == "application" {
+                    metadata.push(&rtp, opts);
+                }
+
+                packets += 1;
+                if opts.max_packets == Some(packets) {
+                    tracing::info!("Got {} packets, stopping", packets);
+                    break Ok(Exit::Stopped);
+                }
+            }
+            Some(Ok(retina::client::PacketItem::SenderReport(sr))) => {
+                log_sender_report(opts, sr.stream_id, sr.rtp_timestamp, sr.ntp_timestamp);
+            }
+            Some(Ok(_)) => {}
+            Some(Err(err)) => break Err(err.into()),
+            None => break Ok(Exit::Ended),
+        }
+    }
+}
+
+/// How long to wait for the NTP clock to sync before playing anyway.
+const NTP_SYNC_TIMEOUT: gst::ClockTime = gst::ClockTime::from_seconds(10);
+
+/// The clock of [PlayerOptions::clock], synced to `ntp_server` if it's NTP.
+async fn pipeline_clock(clock: PipelineClock, ntp_server: &str) -> Result<gst::Clock> {
+    match clock {
+        PipelineClock::System => {
+            // Not the shared one from obtain(), which every other pipeline defaults to
+            let clock = gst::glib::Object::new::<gst::SystemClock>(&[(
+                "clock-type",
+                &gst::ClockType::Realtime,
+            )])?;
+
+            Ok(clock.upcast())
+        }
+        PipelineClock::Ntp => {
+            // NtpClock takes an IP address
+            let addr = tokio::net::lookup_host(ntp_server)
+                .await
+                .map_err(|err| eyre!("Can't resolve the NTP server {}: {}", ntp_server, err))?
+                .next()
+                .ok_or_else(|| eyre!("The NTP server {} has no address", ntp_server))?;
+
+            let clock = gst_net::NtpClock::new(
+                None,
+                &addr.ip().to_string(),
+                i32::from(addr.port()),
+                gst::ClockTime::ZERO,
+            )
+            .upcast::<gst::Clock>();
+
+            let synced = {
+                let clock = clock.clone();
+                tokio::task::spawn_blocking(move || clock.wait_for_sync(NTP_SYNC_TIMEOUT)).await?
+            };
+            match synced {
+                Ok(()) => tracing::info!("Synced to the NTP server {}", addr),
+                Err(_) => tracing::warn!(
+                    "Not synced to the NTP server {} after {}, playing anyway",
+                    addr,
+                    NTP_SYNC_TIMEOUT
+                ),
+            }
+
+            Ok(clock)
+        }
+    }
+}
+
+/// Base time of every pipeline on `kind` of clock, so their running times agree: the
+/// clock's time when the first of them started.
+fn shared_base_time(kind: PipelineClock, clock: &gst::Clock) -> Result<gst::ClockTime> {
+    static BASE_TIMES: std::sync::Mutex<[Option<gst::ClockTime>; 2]> =
+        std::sync::Mutex::new([None; 2]);
+
+    let mut base_times = BASE_TIMES.lock().unwrap();
+    let base_time = &mut base_times[kind as usize];
+
+    match *base_time {
+        Some(base_time) => Ok(base_time),
+        None => {
+            let now = clock.time().ok_or_else(|| eyre!("The clock has no time"))?;
+            Ok(*base_time.insert(now))
+        }
+    }
+}
+
+/// The [BranchOptions] `opts` ask for.
+fn branch_options(opts: &PlayerOptions) -> BranchOptions {
+    BranchOptions {
+        snapshot: opts.snapshot.clone(),
+        dump_es: opts.dump_es.clone(),
+        snapshot_interval: opts.snapshot_interval.map(|interval| {
+            let prefix = opts
+                .name
+                .as_ref()
+                .map(|name| format!("{}-", name))
+                .unwrap_or_default();
+            (interval, opts.snapshot_dir.clone(), prefix)
+        }),
+        h264_format: opts.h264_format,
+        record: (opts.record.is_some() || opts.rtmp.is_some()) && opts.snapshot.is_none(),
+        output_format: opts.output_format,
+        segmented: opts.segment_duration.is_some(),
+        rtmp: opts.rtmp.is_some() && opts.snapshot.is_none(),
+        headless: opts.headless,
+        decoder: opts.decoder.clone(),
+        hwaccel: opts.hwaccel,
+        video_sink: opts.video_sink.clone(),
+        mute: opts.mute,
+        window_title: opts.window_title.clone(),
+        window_geometry: opts.window_geometry,
+        verbose_caps: opts.verbose_caps,
+        jitter_latency: opts.jitter_latency.filter(|_| !opts.low_latency),
+        queue_size: opts.queue_size,
+        low_latency: opts.low_latency,
+        recover_on_error: opts.recover_on_error,
+        overrides: opts.branches.clone(),
+    }
+}
+
+/// Adds the recording muxer, if recording or restreaming; branches with an unlinked src
+/// pad get linked to it.
+fn add_recording_muxer(
+    pipeline: &gst::Pipeline,
+    opts: &PlayerOptions,
+    session_i: u64,
+) -> Result<()> {
+    if let (Some(url), None) = (&opts.rtmp, &opts.snapshot) {
+        let mux = gst::ElementFactory::make("flvmux", Some("recmux"))?;
+        let rtmpsink = gst::ElementFactory::make("rtmpsink", None)?;
+
+        mux.set_property("streamable", true);
+        rtmpsink.set_property("location", url.as_str());
+
+        pipeline.add_many(&[&mux, &rtmpsink])?;
+        gst::Element::link_many(&[&mux, &rtmpsink])?;
+    } else if let (Some(path), None) = (&opts.record, &opts.snapshot) {
+        let path = session_path(path, session_i);
+        let location = &*path.to_string_lossy();
+
+        if let Some(segment_duration) = opts.segment_duration {
+            let mux = gst::ElementFactory::make(opts.output_format.muxer(), None)?;
+            let splitmux = gst::ElementFactory::make("splitmuxsink", Some("recmux"))?;
+
+            splitmux.set_property("location", location);
+            splitmux.set_property("max-size-time", segment_duration.as_nanos() as u64);
+            splitmux.set_property("muxer", &mux);
+
+            pipeline.add(&splitmux)?;
+        } else {
+            let mux = gst::ElementFactory::make(opts.output_format.muxer(), Some("recmux"))?;
+            let filesink = gst::ElementFactory::make("filesink", None)?;
+
+            filesink.set_property("location", location);
+
+            pipeline.add_many(&[&mux, &filesink])?;
+            gst::Element::link_many(&[&mux, &filesink])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds the bin the first of `launches` that can be made describes to `pipeline`, links
+/// `pad` of `media` to it, and plays it.
+fn add_branch(
+    pipeline: &gst::Pipeline,
+    pad: &gst::Pad,
+    launches: &[String],
+    media: &str,
+    branch: &BranchOptions,
+) -> Result<gst::Bin> {
+    let bin = launches
+        .iter()
+        .find_map(
+            |launch| match gst::parse_bin_from_description(launch, true) {
+                Ok(bin) => Some(bin),
+                Err(err) => {
+                    tracing::warn!("Can't make `{}`, falling back: {}", launch, err);
+                    None
+                }
+            },
+        )
+        .ok_or_else(|| eyre!("Can't make any branch"))?;
+
+    pipeline.add(&bin)?;
+
+    if let Err(err) = link_branch(pipeline, pad, &bin, media, branch) {
+        remove_branch(pipeline, &bin);
+        return Err(err);
+    }
+
+    Ok(bin)
+}
+
+/// Links `pad` of `media` to `bin`, and `bin` to the recording muxer if it has a src
+/// pad, and plays it.
+fn link_branch(
+    pipeline: &gst::Pipeline,
+    pad: &gst::Pad,
+    bin: &gst::Bin,
+    media: &str,
+    branch: &BranchOptions,
+) -> Result<()> {
+    branch::log_first_keyframe(bin);
+    branch::log_video_format(bin);
+
+    if branch.snapshot.is_some() {
+        branch::wait_for_keyframe(bin);
+    }
+
+    if let Some((_, dir, prefix)) = &branch.snapshot_interval {
+        branch::save_thumbnails(bin, dir.clone(), prefix.clone());
+    }
+
+    if branch.verbose_caps {
+        branch::log_caps(bin);
+    }
+
+    if branch.low_latency {
+        branch::unsync_sinks(bin);
+    }
+
+    if let Some(title) = &branch.window_title {
+        branch::set_window_title(bin, title);
+    }
+
+    if let Some(geometry) = branch.window_geometry {
+        branch::set_window_geometry(bin, geometry);
+    }
+
+    let sink = bin
+        .static_pad("sink")
+        .ok_or_else(|| eyre!("Branch has no sink pad"))?;
+    pad.link(&sink)?;
+
+    if branch.recover_on_error {
+        branch::isolate_errors(pad);
+    }
+
+    if let Some(src) = bin.static_pad("src") {
+        let mux = pipeline
+            .by_name("recmux")
+            .ok_or_else(|| eyre!("Branch has a src pad, but there's no recording muxer"))?;
+        let mux_sink = mux
+            .request_pad_simple(&branch.mux_pad_template(media))
+            .ok_or_else(|| eyre!("Recording muxer has no {} pad", media))?;
+        src.link(&mux_sink)?;
+    }
+
+    bin.set_state(gst::State::Playing)?;
+
+    Ok(())
+}
+
+/// How soon after a restart another error of the same branch is fatal, so a branch
+/// that can't recover doesn't restart forever.
+const RECOVER_WINDOW: Duration = Duration::from_secs(10);
+
+/// The branch bin `msg` came from, if restarting it could recover the pipeline: it
+/// isn't linked to the recording muxer, whose stream a restart would break.
+fn errored_branch(pipeline: &gst::Pipeline, msg: &gst::Message) -> Option<gst::Bin> {
+    let mut element = msg.src()?;
+    loop {
+        let parent = element.parent()?;
+        if &parent == pipeline.upcast_ref::<gst::Object>() {
+            break;
+        }
+        element = parent;
+    }
+
+    // splitmuxsink is a bin too, but not a branch
+    if element.type_() != gst::Bin::static_type() {
+        return None;
+    }
+
+    let bin = element.downcast::<gst::Bin>().ok()?;
+    if bin.static_pad("src").is_some() {
+        return None;
+    }
+
+    Some(bin)
+}
+
+/// Plays `bin` again from NULL, unlinked meanwhile so [branch::isolate_errors] drops
+/// what `rtpptdemux` sends it. Relinking resends the caps and segment.
+fn restart_branch(bin: &gst::Bin) -> Result<()> {
+    let sink = bin
+        .static_pad("sink")
+        .ok_or_else(|| eyre!("{} has no sink pad", bin.name()))?;
+    let peer = sink
+        .peer()
+        .ok_or_else(|| eyre!("{} isn't linked", bin.name()))?;
+
+    peer.unlink(&sink)?;
+    bin.set_state(gst::State::Null)?;
+    bin.sync_state_with_parent()?;
+    peer.link(&sink)?;
+
+    Ok(())
+}
+
+/// Unlinks `bin` from `rtpptdemux` and the recording muxer, and takes it out of
+/// `pipeline`.
+fn remove_branch(pipeline: &gst::Pipeline, bin: &gst::Bin) {
+    if let Some(sink) = bin.static_pad("sink") {
+        if let Some(peer) = sink.peer() {
+            let _ = peer.unlink(&sink);
+        }
+    }
+
+    // The muxer would wait on a pad left behind, so the recording never finalizes
+    if let Some(src) = bin.static_pad("src") {
+        if let Some(mux_sink) = src.peer() {
+            let _ = src.unlink(&mux_sink);
+
+            if let Some(mux) = mux_sink.parent_element() {
+                mux.release_request_pad(&mux_sink);
+            }
+        }
+    }
+
+    let _ = bin.set_state(gst::State::Null);
+    let _ = pipeline.remove(bin);
+}
+
+/// The caps of RTP packets from `stream`.
+fn rtp_caps(
+    stream: &Stream,
+    clock_rate: u32,
+    fmtp: &HashMap<u8, Vec<(String, String)>>,
+    opts: &PlayerOptions,
+) -> gst::Caps {
+    let caps = gst::Caps::builder("application/x-rtp")
+        .field("clock-rate", clock_rate as i32)
+        .field("payload", stream.rtp_payload_type as i32)
+        .field("media", &stream.media)
+        .field("encoding-name", &encoding_name(stream, opts).to_uppercase());
+
+    // Opus always has 2 in its rtpmap, whatever it decodes to
+    let channels = match (stream.media.as_str(), encoding_name(stream, opts)) {
+        (_, "opus") => stream.channels.or(NonZeroU16::new(2)),
+        ("audio", _) => stream.channels.or(Some(opts.audio_channels)),
+        _ => stream.channels,
+    };
+
+    let caps = if let Some(channels) = channels.map(NonZeroU16::get) {
+        caps.field("channels", channels as i32)
+    } else {
+        caps
+    };
+
+    // e.g. AAC `config` and `sizelength`, which the depayloaders need.
+    let caps = fmtp
+        .get(&stream.rtp_payload_type)
+        .into_iter()
+        .flatten()
+        .fold(caps, |caps, (key, value)| caps.field(key, value));
+
+    // application/x-rtp, payload=(int)96, media=(string)video, clock-rate=(int)90000, encoding-name=(string)H264
+    caps.build()
+}
+
+/// Encoding name of `stream`, lower case as Retina has them, after
+/// [PlayerOptions::map_pt].
+fn encoding_name<'a>(stream: &'a Stream, opts: &'a PlayerOptions) -> &'a str {
+    opts.map_pt
+        .get(&stream.rtp_payload_type)
+        .map_or(stream.encoding_name.as_str(), String::as_str)
+}
+
+/// Writes `pipeline` as a DOT graph to `$GST_DEBUG_DUMP_DOT_DIR`, if that's set.
+fn dump_dot(pipeline: &gst::Pipeline, name: &str) {
+    pipeline.debug_to_dot_file_with_ts(gst::DebugGraphDetails::all(), name);
+}
+
+/// `name`, prefixed with the pipeline's name if it has one.
+fn element_name(opts: &PlayerOptions, name: &str) -> String {
+    match &opts.name {
+        Some(prefix) => format!("{}-{}", prefix, name),
+        None => name.to_owned(),
+    }
+}
+
+/// Awaits `fut`, failing if it takes longer than `timeout`.
+async fn timeout<T, E>(
+    timeout: Option<Duration>,
+    what: &str,
+    fut: impl Future<Output = std::result::Result<T, E>>,
+) -> Result<T>
+where
+    color_eyre::Report: From<E>,
+{
+    match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, fut).await {
+            Ok(res) => Ok(res?),
+            Err(_) => bail!("{} timed out after {:?}", what, timeout),
+        },
+        None => Ok(fut.await?),
+    }
+}
+
+/// `url` with its host resolved to an address of `family`, unless that's
+/// [IpFamily::Auto] or it's an address already.
+async fn resolve(url: &url::Url, family: IpFamily) -> Result<url::Url> {
+    let v6 = match family {
+        IpFamily::Auto => return Ok(url.clone()),
+        IpFamily::V4 => false,
+        IpFamily::V6 => true,
+    };
+
+    let domain = match url.host() {
+        Some(url::Host::Domain(domain)) => domain,
+        _ => return Ok(url.clone()),
+    };
+
+    let port = url.port().unwrap_or(554);
+    let addr = tokio::net::lookup_host((domain, port))
+        .await?
+        .find(|addr| addr.is_ipv6() == v6)
+        .ok_or_else(|| eyre!("{} has no IPv{} address", domain, if v6 { 6 } else { 4 }))?;
+
+    tracing::debug!("Connecting to {} at {}", domain, addr.ip());
+
+    let mut resolved = url.clone();
+    resolved
+        .set_ip_host(addr.ip())
+        .map_err(|()| eyre!("Can't put {} in {}", addr.ip(), log_url(url)))?;
+
+    Ok(resolved)
+}
+
+/// [SetupOptions] with the requested transport, if any.
+fn setup_options(transport: Option<TransportKind>) -> SetupOptions {
+    let options = SetupOptions::default();
+
+    match transport {
+        Some(TransportKind::Udp) => {
+            options.transport(Transport::Udp(UdpTransportOptions::default()))
+        }
+        Some(TransportKind::Tcp) => {
+            options.transport(Transport::Tcp(TcpTransportOptions::default()))
+        }
+        None => options,
+    }
+}
+
+fn play_options(opts: &PlayerOptions) -> PlayOptions {
+    let mut options = PlayOptions::default()
+        .initial_timestamp(opts.initial_timestamp.into())
+        .ignore_zero_seq(opts.ignore_zero_seq);
+
+    if opts.lenient {
+        options = options.unknown_rtcp_ssrc(UnknownRtcpSsrcPolicy::DropPackets);
+    }
+
+    match opts.enforce_timestamps {
+        Some(max_jump_secs) => options.enforce_timestamps_with_max_jump_secs(max_jump_secs),
+        None => options,
+    }
+}
+
+/// The first multicast address in the SDP's `c=` lines, if any.
+fn multicast_group(sdp: &[u8]) -> Option<std::net::IpAddr> {
+    String::from_utf8_lossy(sdp).lines().find_map(|line| {
+        // e.g. `c=IN IP4 239.0.0.1/255`
+        let addr = line.strip_prefix("c=IN ")?.split_whitespace().nth(1)?;
+        let addr = addr.split('/').next()?.parse::<std::net::IpAddr>().ok()?;
+
+        addr.is_multicast().then_some(addr)
+    })
+}
+
+/// Logs the H.264 SPS and PPS in the `sprop-parameter-sets` of `fmtp`, which
+/// `rtph264depay` hands the decoder for cameras that don't send them in-band.
+fn log_parameter_sets(fmtp: Option<&Vec<(String, String)>>) {
+    let sets = fmtp
+        .into_iter()
+        .flatten()
+        .find(|(key, _)| key == "sprop-parameter-sets");
+
+    let sets = match sets {
+        Some((_, sets)) => sets,
+        None => {
+            tracing::info!("No H.264 parameter sets in the SDP; they have to come in-band");
+            return;
+        }
+    };
+
+    for set in sets.split(',') {
+        match base64::decode(set) {
+            // profile_idc, constraint flags and level_idc follow the NAL header
+            Ok(nal) if nal.first().map(|b| b & 0x1f) == Some(7) && nal.len() >= 4 => {
+                tracing::info!(
+                    "SDP SPS: profile {} level {}, {} bytes",
+                    nal[1],
+                    nal[3],
+                    nal.len()
+                );
+            }
+            Ok(nal) if nal.first().map(|b| b & 0x1f) == Some(8) => {
+                tracing::info!("SDP PPS: {} bytes", nal.len());
+            }
+            Ok(nal) => tracing::warn!("SDP parameter set isn't an SPS or PPS: {:02x?}", nal),
+            Err(err) => tracing::warn!("Bad SDP parameter set {:?}: {}", set, err),
+        }
+    }
+}
+
+/// The bitrate in kbps of each media section of the SDP, from its `b=AS` line, else
+/// its `b=TIAS` one.
+fn bitrates(sdp: &[u8]) -> Vec<Option<u32>> {
+    let mut bitrates: Vec<(Option<u32>, Option<u32>)> = Vec::new();
+
+    for line in String::from_utf8_lossy(sdp).lines() {
+        if line.starts_with("m=") {
+            bitrates.push((None, None));
+        }
+
+        // Session level ones are for all the streams together
+        let (as_, tias) = match bitrates.last_mut() {
+            Some(bitrate) => bitrate,
+            None => continue,
+        };
+
+        if let Some(kbps) = line.strip_prefix("b=AS:") {
+            *as_ = kbps.trim().parse().ok();
+        } else if let Some(bps) = line.strip_prefix("b=TIAS:") {
+            *tias = bps.trim().parse::<u32>().ok().map(|bps| bps / 1000);
+        }
+    }
+
+    bitrates
+        .into_iter()
+        .map(|(as_, tias)| as_.or(tias))
+        .collect()
+}
+
+/// Collects the `a=fmtp` parameters of each payload type in the SDP, keyed the way
+/// `rtspsrc` puts them in its caps.
+fn fmtp(sdp: &[u8]) -> HashMap<u8, Vec<(String, String)>> {
+    let mut fmtp = HashMap::new();
+
+    for line in String::from_utf8_lossy(sdp).lines() {
+        let (pt, params) = match line
+            .strip_prefix("a=fmtp:")
+            .and_then(|line| line.split_once(' '))
+        {
+            Some(fmtp) => fmtp,
+            None => continue,
+        };
+
+        let pt = match pt.parse::<u8>() {
+            Ok(pt) => pt,
+            Err(_) => continue,
+        };
+
+        let params = params
+            .split(';')
+            .filter_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                Some((key.trim().to_lowercase(), value.trim().to_owned()))
+            })
+            .collect();
+
+        fmtp.insert(pt, params);
+    }
+
+    fmtp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bitrates, fmtp, WindowGeometry};
+
+    const SDP: &[u8] = b"v=0\r
+o=- 0 0 IN IP4 10.0.0.1\r
+s=Camera\r
+b=AS:5000\r
+t=0 0\r
+m=video 0 RTP/AVP 96\r
+b=AS:2048\r
+a=rtpmap:96 H264/90000\r
+a=fmtp:96 packetization-mode=1; Profile-Level-Id=640028;sprop-parameter-sets=Z2QAKKw=,aO48sA==\r
+m=video 0 RTP/AVP 97\r
+b=TIAS:512000\r
+a=rtpmap:97 H264/90000\r
+m=audio 0 RTP/AVP 8\r
+a=rtpmap:8 PCMA/8000\r
+";
+
+    #[test]
+    fn bitrates_per_stream() {
+        // Not the session level 5000
+        assert_eq!(bitrates(SDP), vec![Some(2048), Some(512), None]);
+    }
+
+    #[test]
+    fn bitrates_prefer_as() {
+        let sdp = b"v=0\r\nm=video 0 RTP/AVP 96\r\nb=TIAS:512000\r\nb=AS:600\r\n";
+        assert_eq!(bitrates(sdp), vec![Some(600)]);
+    }
+
+    #[test]
+    fn fmtp_params() {
+        let fmtp = fmtp(SDP);
+
+        assert_eq!(
+            fmtp[&96],
+            vec![
+                ("packetization-mode".to_owned(), "1".to_owned()),
+                ("profile-level-id".to_owned(), "640028".to_owned()),
+                (
+                    "sprop-parameter-sets".to_owned(),
+                    "Z2QAKKw=,aO48sA==".to_owned()
+                ),
+            ]
+        );
+        assert!(!fmtp.contains_key(&97));
+    }
+
+    fn geometry(s: &str) -> (u32, u32, i32, i32) {
+        let WindowGeometry {
+            width,
+            height,
+            x,
+            y,
+        } = s.parse::<WindowGeometry>().unwrap();
+
+        (width, height, x, y)
+    }
+
+    #[test]
+    fn window_geometry_parses() {
+        assert_eq!(geometry("1280x720"), (1280, 720, 0, 0));
+        assert_eq!(geometry("640x480+100+50"), (640, 480, 100, 50));
+    }
+
+    #[test]
+    fn window_geometry_rejects() {
+        for s in ["", "1280", "1280x", "x720", "640x480+100", "640x480+a+b"] {
+            assert!(s.parse::<WindowGeometry>().is_err(), "{:?}", s);
+        }
+    }
+}
@@ -1,34 +1,481 @@
-use std::num::NonZeroU16;
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::num::{NonZeroU16, NonZeroU32};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use clap::Parser;
 
-use futures_util::StreamExt;
-use retina::client::*;
+use tracing::Instrument;
+
+use tracing_appender::non_blocking::WorkerGuard;
 use tracing_error::ErrorLayer;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{fmt, EnvFilter};
 
-use gst::prelude::*;
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+
+use video_stream::{
+    Codec, H264Format, Hwaccel, InitialTimestamp, IpFamily, OutputFormat, PcapWriter,
+    PipelineClock, PlayerOptions, RtsPlayer, TransportKind, WindowGeometry,
+};
 
-use color_eyre::{eyre::bail, Result};
+/// Stream ids each URL gets in the pcap file.
+const PCAP_STREAMS_PER_URL: usize = 16;
 
 #[derive(Debug, Parser)]
 struct Args {
-    /// `rtsp://` URL to connect to.
-    #[clap(long, env, parse(try_from_str))]
-    url: url::Url,
+    /// `rtsp://` URL to connect to; repeat to play several cameras at once. Without a
+    /// port it's 554.
+    #[clap(
+        long,
+        env,
+        parse(try_from_str = rtsp_url),
+        required_unless_present_any = &["file", "check"],
+        multiple_occurrences = true
+    )]
+    url: Vec<url::Url>,
+
+    /// Play this MP4 or MKV file through the same branches instead, e.g. to test
+    /// decoding without a camera.
+    #[clap(long, parse(from_os_str), conflicts_with = "url")]
+    file: Option<PathBuf>,
+
+    /// Check that the GStreamer elements of each codec, and of the options given, are
+    /// installed, and exit.
+    #[clap(long)]
+    check: bool,
+
+    /// Play `--file` over and over.
+    #[clap(long = "loop", requires = "file")]
+    loop_file: bool,
 
     /// Username to send if the server requires authentication.
     #[clap(long, env)]
     username: Option<String>,
 
-    /// Password; requires username.
+    /// Password; requires username. Shows up in process listings and shell history, so
+    /// prefer the `PASSWORD` environment variable or --password-file.
     #[clap(long, env, requires = "username")]
     password: Option<String>,
 
+    /// Read the password from this file, e.g. a mounted secret; a trailing newline is
+    /// dropped.
+    #[clap(long, env, requires = "username", conflicts_with = "password")]
+    password_file: Option<PathBuf>,
+
+    /// `User-Agent` to send, for cameras that reject the default one.
+    #[clap(long, default_value = "Retina sdp example")]
+    user_agent: String,
+
+    /// RTP transport to request; Retina picks one by default.
+    ///
+    /// UDP sockets get the system's default receive buffer; if high bitrate streams lose
+    /// packets, raise `net.core.rmem_default` or use TCP.
+    #[clap(long, arg_enum)]
+    transport: Option<TransportKind>,
+
+    /// Connect to the camera over IPv4 or IPv6 only, e.g. when its IPv6 address is
+    /// unreachable.
+    #[clap(long, arg_enum, default_value = "auto")]
+    ip_family: IpFamily,
+
+    /// What to do with the PLAY response's `RTP-Info` initial timestamps. If the first
+    /// timestamps jump, or A/V start out of sync, try `ignore`; if PLAY fails because a
+    /// stream has none, try `permissive`.
+    #[clap(long, arg_enum, default_value = "default")]
+    initial_timestamp: InitialTimestamp,
+
+    /// Drop packets with sequence number 0, for cameras that send a stray one before the
+    /// stream proper and so log lost packets right at the start.
+    #[clap(long)]
+    ignore_zero_seq: bool,
+
+    /// Fail, and reconnect with --reconnect, when a stream's timestamps go backwards or
+    /// jump forward more than this many seconds. For cameras whose timestamps jump
+    /// mid-stream and freeze or stall the pipeline; off by default.
+    #[clap(long, value_name = "MAX_JUMP_SECS")]
+    enforce_timestamps: Option<NonZeroU32>,
+
+    /// Video codec to prefer when the camera offers several.
+    #[clap(long, arg_enum, default_value = "auto")]
+    codec: Codec,
+
+    /// Index of the video stream to play, e.g. a camera's substream.
+    #[clap(long, conflicts_with = "stream-control")]
+    video_stream: Option<usize>,
+
+    /// Control URL (or its last part, e.g. `trackID=2`) of the video stream to play.
+    #[clap(long)]
+    stream_control: Option<String>,
+
+    /// Play the video stream of the highest bitrate up to this, by the SDP's `b=` lines,
+    /// e.g. a substream over a metered link.
+    #[clap(
+        long,
+        value_name = "KBPS",
+        conflicts_with_all = &["video-stream", "stream-control"]
+    )]
+    max_bitrate: Option<u32>,
+
+    /// Record the video and audio to this file while displaying it, or without with
+    /// `--headless`; G.711 audio only to MKV. After a --reconnect, to `NAME-1.EXT` and so on.
+    #[clap(long, parse(from_os_str))]
+    record: Option<PathBuf>,
+
+    /// Container to record in; MKV and TS stay playable if recording gets cut off.
+    #[clap(long, arg_enum, default_value = "mp4")]
+    output_format: OutputFormat,
+
+    /// Record into files about this many seconds long, named by `--record-template`.
+    #[clap(long, requires = "record-template")]
+    segment_duration: Option<u64>,
+
+    /// Names of the files recorded, with a counter, e.g. `clip-%05d.mkv`.
+    #[clap(
+        long,
+        parse(from_os_str),
+        conflicts_with = "record",
+        requires = "segment-duration"
+    )]
+    record_template: Option<PathBuf>,
+
+    /// Push the video to this RTMP server instead of recording it, e.g.
+    /// `rtmp://live.example.com/app/key`. Non-H.264 video gets re-encoded.
+    #[clap(long, conflicts_with_all = &["record", "record-template"])]
+    rtmp: Option<url::Url>,
+
+    /// Write H.264 or H.265 video to this file undecoded, as an elementary stream to mux
+    /// or transcode later; displaying it too unless `--headless`.
+    #[clap(
+        long,
+        parse(from_os_str),
+        conflicts_with_all = &["record", "record-template", "rtmp"]
+    )]
+    dump_es: Option<PathBuf>,
+
+    /// Stream format of H.264 `--dump-es` writes: `avc` for decoders that take length
+    /// prefixed NAL units.
+    #[clap(long, arg_enum, default_value = "byte-stream")]
+    h264_format: H264Format,
+
+    /// Save the first video keyframe to this JPEG file and exit.
+    #[clap(
+        long,
+        parse(from_os_str),
+        conflicts_with_all = &["record", "record-template", "rtmp", "dump-es"]
+    )]
+    snapshot: Option<PathBuf>,
+
+    /// Save a JPEG of the video every this many seconds while playing, to
+    /// --snapshot-dir, named by the Unix time; e.g. for time-lapses or thumbnails.
+    #[clap(long, conflicts_with = "snapshot")]
+    snapshot_interval: Option<u64>,
+
+    /// Directory of the --snapshot-interval JPEGs; with several cameras, their names
+    /// start with `camN-`.
+    #[clap(long, parse(from_os_str), default_value = ".")]
+    snapshot_dir: PathBuf,
+
+    /// Save the SDP to this file.
+    #[clap(long, parse(from_os_str))]
+    save_sdp: Option<PathBuf>,
+
+    /// Print the SDP and exit without setting up any stream.
+    #[clap(long)]
+    dump_sdp_and_exit: bool,
+
+    /// Print the streams as a JSON array and exit without setting any up.
+    #[clap(long, conflicts_with = "dump-sdp-and-exit")]
+    print_streams_json: bool,
+
+    /// Play for `--duration` seconds (5 by default) without displaying, then print
+    /// whether the streams it plays got through depaying and decoding, and exit.
+    ///
+    /// That's the video stream and audio stream it'd pick otherwise, as with
+    /// `--video-stream`; others aren't set up. `--print-streams-json` lists all of them.
+    #[clap(long, conflicts_with_all = &["raw-stdout", "file"])]
+    probe_only: bool,
+
+    /// Don't decode or display video, e.g. on a server without a display.
+    #[clap(long)]
+    headless: bool,
+
+    /// Log the caps negotiated at every pad of the stream branches.
+    #[clap(long)]
+    verbose_caps: bool,
+
+    /// Log the timestamps of each RTCP sender report.
+    ///
+    /// Retina only receives RTCP, and has no options for it; it sends none, not even
+    /// receiver reports.
+    #[clap(long)]
+    log_sender_reports: bool,
+
+    /// Element decoding video, e.g. `vaapidecodebin` or `avdec_h264`.
+    #[clap(long, default_value = "decodebin")]
+    decoder: String,
+
+    /// Pick the video decoder for each codec by hardware, instead of `--decoder`.
+    #[clap(long, arg_enum, conflicts_with = "decoder")]
+    hwaccel: Option<Hwaccel>,
+
+    /// Element displaying video, e.g. `glimagesink`, `ximagesink` or `kmssink`.
+    #[clap(long, default_value = "autovideosink")]
+    video_sink: String,
+
+    /// Title of the video window, where the sink supports one, e.g. `ximagesink`.
+    #[clap(long)]
+    window_title: Option<String>,
+
+    /// Size and position of the video window, as `WxH+X+Y`, e.g. `640x360+0+0`.
+    /// `glimagesink` moves and sizes its window; others only draw in that part of it.
+    #[clap(long)]
+    window_geometry: Option<WindowGeometry>,
+
+    /// Don't play anything, but write the received RTP packets to stdout, each after its
+    /// length as 2 big-endian bytes (RFC 4571 framing). Needs no GStreamer plugins.
+    #[clap(
+        long,
+        conflicts_with_all = &["pcap", "file", "record", "record-template", "rtmp", "snapshot"]
+    )]
+    raw_stdout: bool,
+
+    /// Also write the received RTP packets to this pcap file.
+    #[clap(long, parse(from_os_str))]
+    pcap: Option<PathBuf>,
+
+    /// Also set up the camera's metadata stream, e.g. ONVIF analytics, and write each of
+    /// its messages to this file, one per line.
+    #[clap(long, parse(from_os_str))]
+    metadata_out: Option<PathBuf>,
+
+    /// Play the audio stream, if the camera has one (default).
+    #[clap(long, overrides_with = "no-audio")]
+    audio: bool,
+
+    /// Don't set up the audio stream.
+    #[clap(long, overrides_with = "audio")]
+    no_audio: bool,
+
+    /// Set up and decode the audio stream, but don't play it; it's still recorded.
+    #[clap(long, conflicts_with = "no-audio")]
+    mute: bool,
+
+    /// Channels of audio whose SDP doesn't say; mono per the RTP spec.
+    #[clap(long, default_value = "1")]
+    audio_channels: NonZeroU16,
+
+    /// Reconnect instead of exiting when the session fails or ends.
+    #[clap(long)]
+    reconnect: bool,
+
+    /// Restart a branch whose element errors, e.g. on a display glitch, instead of
+    /// failing the session; it still fails if the same branch errors again within 10s.
+    #[clap(long)]
+    recover_on_error: bool,
+
+    /// Longest wait between reconnect attempts, in seconds.
+    #[clap(long, default_value = "60")]
+    reconnect_max_delay: u64,
+
+    /// Retry connecting at startup this many times before giving up.
+    #[clap(long, default_value = "0")]
+    startup_retries: u32,
+
+    /// Wait between startup retries, in seconds.
+    #[clap(long, default_value = "5")]
+    startup_retry_delay: u64,
+
+    /// Fail DESCRIBE, SETUP or PLAY requests taking longer than this many seconds.
+    #[clap(long)]
+    connect_timeout: Option<u64>,
+
+    /// Timeout of DESCRIBE in seconds, instead of --connect-timeout.
+    #[clap(long)]
+    describe_timeout: Option<u64>,
+
+    /// Timeout of each SETUP in seconds, instead of --connect-timeout.
+    #[clap(long)]
+    setup_timeout: Option<u64>,
+
+    /// Timeout of PLAY in seconds, instead of --connect-timeout, e.g. for a camera slow
+    /// to start streaming.
+    #[clap(long)]
+    play_timeout: Option<u64>,
+
+    /// Fail the session when no packet arrives for this many seconds.
+    #[clap(long)]
+    read_timeout: Option<u64>,
+
+    /// Fail the session when no video frame gets displayed for this many seconds, e.g.
+    /// because the decoder hung; with --reconnect, that restarts the pipeline.
+    #[clap(long)]
+    stall_timeout: Option<u64>,
+
+    /// Longest to wait for the camera to acknowledge TEARDOWN on exit, in seconds.
+    #[clap(long, default_value = "5")]
+    teardown_timeout: u64,
+
+    /// Smooth out late and reordered packets with a jitter buffer of this many milliseconds.
+    #[clap(long)]
+    jitter_latency: Option<u64>,
+
+    /// Most milliseconds of data the queue before each sink holds.
+    #[clap(long)]
+    queue_size: Option<u64>,
+
+    /// Nanoseconds to delay every stream by, giving depaying and decoding time before the
+    /// sinks; 0 for the least latency, at the risk of late frames getting dropped.
+    #[clap(long, default_value = "1000000000")]
+    pad_offset: u64,
+
+    /// Milliseconds to delay audio by relative to video, to correct a camera's constant
+    /// lip-sync error; negative to play it earlier.
+    #[clap(long, allow_hyphen_values = true, default_value = "0")]
+    av_sync_offset: i64,
+
+    /// Wallclock to run the pipelines on, with one base time, so cameras with NTP-synced
+    /// clocks play in sync: `system` for the realtime clock of an NTP-synced host, `ntp`
+    /// to sync to --ntp-server.
+    #[clap(long, arg_enum)]
+    clock: Option<PipelineClock>,
+
+    /// `host:port` of the NTP server of `--clock ntp`.
+    #[clap(long, default_value = "pool.ntp.org:123")]
+    ntp_server: String,
+
+    /// Lowest latency over smoothness: no jitter buffer or pad offset, unsynced sinks,
+    /// and only the latest buffer queued before them.
+    #[clap(long, conflicts_with_all = &["jitter-latency", "queue-size"])]
+    low_latency: bool,
+
+    /// Milliseconds of latency the RTP source reports, which the sinks sync with.
+    #[clap(long)]
+    appsrc_min_latency: Option<u64>,
+
+    /// Most milliseconds of latency the RTP source reports.
+    #[clap(long)]
+    appsrc_max_latency: Option<u64>,
+
+    /// Bytes the RTP source queues before reading pauses.
+    #[clap(long, default_value = "4194304")]
+    appsrc_max_bytes: u64,
+
+    /// Timestamp buffers with their arrival time instead of their RTP timestamps.
+    #[clap(long)]
+    do_timestamp: bool,
+
+    /// For testing: drop this fraction (0 to 1) of the RTP packets before the pipeline.
+    #[clap(long, hide = true, default_value = "0")]
+    simulate_loss: f64,
+
+    /// For testing: delay this fraction (0 to 1) of the RTP packets past their
+    /// stream's next one.
+    #[clap(long, hide = true, default_value = "0")]
+    simulate_reorder: f64,
+
+    /// For testing: seed of the packets --simulate-loss and --simulate-reorder pick.
+    #[clap(long, hide = true, default_value = "0")]
+    seed: u64,
+
+    /// Log the RTP timestamp, PTS and arrival time of every this many packets per stream.
+    #[clap(long)]
+    trace_timestamps: Option<u64>,
+
+    /// Log packet counts and bitrates of each stream every this many seconds.
+    #[clap(long)]
+    stats_interval: Option<u64>,
+
+    /// Serve `/healthz` and `/stats` over HTTP on this address, e.g. `0.0.0.0:8080`.
+    #[clap(long)]
+    http_status: Option<SocketAddr>,
+
+    /// Serve Prometheus metrics at `/metrics` on this address, e.g. `0.0.0.0:9100`.
+    #[clap(long)]
+    metrics: Option<SocketAddr>,
+
+    /// Write the pipeline as DOT graphs to this directory once playing, as branches get
+    /// added, and on errors.
+    #[clap(long, parse(from_os_str))]
+    dump_dot: Option<PathBuf>,
+
+    /// TOML file of launch descriptions to play streams with, by RTP encoding-name,
+    /// e.g. `H264 = "rtph264depay ! avdec_h264 ! autovideosink"`.
+    #[clap(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Treat an RTP payload type as this encoding-name, e.g. `96=H264`, for cameras whose
+    /// SDP gets it wrong; repeat for several.
+    #[clap(
+        long,
+        value_name = "PT=ENCODING",
+        parse(try_from_str = pt_mapping),
+        multiple_occurrences = true
+    )]
+    map_pt: Vec<(u8, String)>,
+
+    /// Stop cleanly after this many seconds.
+    #[clap(long)]
+    duration: Option<u64>,
+
+    /// Fail instead of discarding streams of unsupported encodings.
+    #[clap(long)]
+    strict: bool,
+
+    /// Drop RTCP of unknown SSRCs, data on unassigned interleaved channels, and RTP
+    /// packets with no payload, instead of failing on them. RTP that Retina can't parse
+    /// still ends the session, so add --reconnect for cameras that send some.
+    #[clap(long)]
+    lenient: bool,
+
+    /// Stop cleanly after receiving this many RTP packets from each camera.
+    #[clap(long)]
+    max_packets: Option<u64>,
+
     /// Filter to log
     #[clap(long, env = "RUST_LOG")]
     log: EnvFilter,
+
+    /// GStreamer debug levels, in `GST_DEBUG` syntax, e.g. `2,rtpjitterbuffer:6`; just
+    /// warnings by default. Its messages are logged through --log, so that has to let
+    /// them through too.
+    #[clap(long, value_name = "SPEC")]
+    gst_debug: Option<String>,
+
+    /// How to write log lines, and the error that ends the program.
+    #[clap(long, arg_enum, default_value = "human")]
+    log_format: LogFormat,
+
+    /// Also log to this file, in --log-format but without colors; rotated files get the
+    /// date or hour appended.
+    #[clap(long, parse(from_os_str))]
+    log_file: Option<PathBuf>,
+
+    /// When to start a new --log-file.
+    #[clap(long, arg_enum, default_value = "never", requires = "log-file")]
+    log_rotate: LogRotate,
+}
+
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+enum LogFormat {
+    /// Colored, with `color_eyre` reports.
+    Human,
+    /// A JSON object per line, for log aggregators.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, clap::ArgEnum)]
+enum LogRotate {
+    Never,
+    Hourly,
+    Daily,
 }
 
 #[tokio::main]
@@ -36,219 +483,547 @@ async fn main() -> Result<()> {
     // Parse Args
     let args = {
         #[cfg(feature = "dotenv")]
-        dotenv().ok();
+        dotenv::dotenv().ok();
         Args::parse()
     };
 
+    let log_format = args.log_format;
+    let log_file = args.log_file.is_some();
+
+    // Flushes the log file when dropped, so only after logging why we exit
+    let mut log_file_guard = None;
+
+    let res = run(args, &mut log_file_guard).await;
+
+    match (res, log_format) {
+        (Err(err), LogFormat::Json) => {
+            tracing::error!(error = %format!("{:#}", err), "Exiting");
+            drop(log_file_guard);
+            std::process::exit(1);
+        }
+        // The report goes to stderr, which doesn't reach the log file
+        (Err(err), LogFormat::Human) if log_file => {
+            tracing::error!("Exiting: {:#}", err);
+            Err(err)
+        }
+        (res, _) => res,
+    }
+}
+
+async fn run(args: Args, log_file_guard: &mut Option<WorkerGuard>) -> Result<()> {
     // Initialize
     {
-        let fmt_layer = fmt::layer().with_target(false);
+        // Keep stdout for the packets
+        let writer = if args.raw_stdout {
+            BoxMakeWriter::new(std::io::stderr)
+        } else {
+            BoxMakeWriter::new(std::io::stdout)
+        };
+
+        let (human_layer, json_layer) = match args.log_format {
+            LogFormat::Human => (
+                Some(fmt::layer().with_target(false).with_writer(writer)),
+                None,
+            ),
+            LogFormat::Json => (None, Some(fmt::layer().json().with_writer(writer))),
+        };
+
+        let file_writer = match &args.log_file {
+            Some(path) => {
+                let dir = match path.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir,
+                    _ => Path::new("."),
+                };
+                let name = path
+                    .file_name()
+                    .ok_or_else(|| eyre!("--log-file {} isn't a file", path.display()))?;
+
+                let appender = match args.log_rotate {
+                    LogRotate::Never => tracing_appender::rolling::never(dir, name),
+                    LogRotate::Hourly => tracing_appender::rolling::hourly(dir, name),
+                    LogRotate::Daily => tracing_appender::rolling::daily(dir, name),
+                };
+
+                let (writer, guard) = tracing_appender::non_blocking(appender);
+                *log_file_guard = Some(guard);
+                Some(writer)
+            }
+            None => None,
+        };
+
+        let (human_file_layer, json_file_layer) = match (file_writer, args.log_format) {
+            (Some(writer), LogFormat::Human) => (
+                Some(
+                    fmt::layer()
+                        .with_ansi(false)
+                        .with_target(false)
+                        .with_writer(writer),
+                ),
+                None,
+            ),
+            (Some(writer), LogFormat::Json) => {
+                (None, Some(fmt::layer().json().with_writer(writer)))
+            }
+            (None, _) => (None, None),
+        };
 
         tracing_subscriber::registry()
             .with(args.log)
-            .with(fmt_layer)
+            .with(human_layer)
+            .with(json_layer)
+            .with(human_file_layer)
+            .with(json_file_layer)
             .with(ErrorLayer::default())
             .init();
 
-        color_eyre::install()?;
+        if let LogFormat::Human = args.log_format {
+            color_eyre::install()?;
+        }
+
+        // GStreamer reads this on init
+        if let Some(dir) = &args.dump_dot {
+            std::env::set_var("GST_DEBUG_DUMP_DOT_DIR", dir);
+        }
 
-        tracing_gst::integrate_events();
-        gst::debug_remove_default_log_function();
-        gst::init()?;
-        gst::debug_set_default_threshold(gst::DebugLevel::Warning);
-        tracing_gst::integrate_spans();
+        if !args.raw_stdout {
+            tracing_gst::integrate_events();
+            gst::debug_remove_default_log_function();
+            gst::init()?;
+            match &args.gst_debug {
+                Some(spec) => gst::debug_set_threshold_from_string(spec, true),
+                None => gst::debug_set_default_threshold(gst::DebugLevel::Warning),
+            }
+            tracing_gst::integrate_spans();
+        }
     }
 
-    let mut session = retina::client::Session::describe(
-        args.url.clone(),
-        retina::client::SessionOptions::default()
-            .creds(creds(args.username, args.password))
-            .user_agent("Retina sdp example".to_owned()),
-    )
-    .await?;
+    let pcap = match &args.pcap {
+        Some(path) => Some(Arc::new(Mutex::new(PcapWriter::create(path)?))),
+        None => None,
+    };
+
+    let branches = match &args.config {
+        Some(path) => read_config(path)?,
+        None => HashMap::new(),
+    };
 
-    tracing::info!("SDP:\n{}\n\n", std::str::from_utf8(session.sdp())?);
+    let password = match &args.password_file {
+        Some(path) => Some(read_password(path)?),
+        None => args.password.clone(),
+    };
 
-    // Make audio and video streams
-    {
-        // Make video stream
-        let video_stream_i = session.streams().iter().position(|s| {
-            if s.media == "video" && s.encoding_name == "h264" {
-                tracing::info!("Using {} video stream", &s.encoding_name);
-                return true;
-            }
+    let several = args.url.len() > 1;
 
-            false
-        });
+    // A file per camera
+    let metadata = match &args.metadata_out {
+        Some(path) => (0..args.url.len().max(1))
+            .map(|i| {
+                let path = if several {
+                    indexed(path, i)
+                } else {
+                    path.clone()
+                };
+                Ok(Arc::new(Mutex::new(std::fs::File::create(path)?)))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+
+    // Options for the `i`th URL; files get the index appended when there are several
+    let opts = |i: usize| {
+        let path = |path: &Option<PathBuf>| match path {
+            Some(path) if several => Some(indexed(path, i)),
+            path => path.clone(),
+        };
+
+        let opts = PlayerOptions::default()
+            .creds(creds(args.username.clone(), password.clone()))
+            .user_agent(args.user_agent.clone())
+            .transport(args.transport)
+            .ip_family(args.ip_family)
+            .initial_timestamp(args.initial_timestamp)
+            .ignore_zero_seq(args.ignore_zero_seq)
+            .enforce_timestamps(args.enforce_timestamps)
+            .codec(args.codec)
+            .video_stream(args.video_stream)
+            .stream_control(args.stream_control.clone())
+            .max_bitrate(args.max_bitrate)
+            .record(path(&args.record).or_else(|| path(&args.record_template)))
+            .output_format(args.output_format)
+            .segment_duration(args.segment_duration.map(Duration::from_secs))
+            .rtmp(args.rtmp.clone())
+            .dump_es(path(&args.dump_es))
+            .h264_format(args.h264_format)
+            .snapshot(path(&args.snapshot))
+            .snapshot_interval(args.snapshot_interval.map(Duration::from_secs))
+            .snapshot_dir(args.snapshot_dir.clone())
+            .save_sdp(path(&args.save_sdp))
+            .headless(args.headless)
+            .verbose_caps(args.verbose_caps)
+            .log_sender_reports(args.log_sender_reports)
+            .decoder(args.decoder.clone())
+            .hwaccel(args.hwaccel)
+            .video_sink(args.video_sink.clone())
+            .window_title(args.window_title.clone())
+            .window_geometry(args.window_geometry)
+            .audio(args.audio || !args.no_audio)
+            .mute(args.mute)
+            .audio_channels(args.audio_channels)
+            .reconnect(args.reconnect)
+            .recover_on_error(args.recover_on_error)
+            .reconnect_max_delay(Duration::from_secs(args.reconnect_max_delay))
+            .startup_retries(args.startup_retries)
+            .startup_retry_delay(Duration::from_secs(args.startup_retry_delay))
+            .stats_interval(args.stats_interval.map(Duration::from_secs))
+            .connect_timeout(args.connect_timeout.map(Duration::from_secs))
+            .describe_timeout(args.describe_timeout.map(Duration::from_secs))
+            .setup_timeout(args.setup_timeout.map(Duration::from_secs))
+            .play_timeout(args.play_timeout.map(Duration::from_secs))
+            .read_timeout(args.read_timeout.map(Duration::from_secs))
+            .stall_timeout(args.stall_timeout.map(Duration::from_secs))
+            .teardown_timeout(Duration::from_secs(args.teardown_timeout))
+            .max_packets(args.max_packets)
+            .strict(args.strict)
+            .lenient(args.lenient)
+            .jitter_latency(args.jitter_latency.map(Duration::from_millis))
+            .queue_size(args.queue_size.map(Duration::from_millis))
+            .pad_offset(Duration::from_nanos(args.pad_offset))
+            .av_sync_offset(args.av_sync_offset * 1_000_000)
+            .clock(args.clock)
+            .ntp_server(args.ntp_server.clone())
+            .low_latency(args.low_latency)
+            .branches(branches.clone())
+            .map_pt(args.map_pt.iter().cloned().collect())
+            .appsrc_min_latency(args.appsrc_min_latency.map(Duration::from_millis))
+            .appsrc_max_latency(args.appsrc_max_latency.map(Duration::from_millis))
+            .appsrc_max_bytes(args.appsrc_max_bytes)
+            .do_timestamp(args.do_timestamp)
+            .simulate_loss(args.simulate_loss)
+            .simulate_reorder(args.simulate_reorder)
+            .seed(args.seed)
+            .trace_timestamps(args.trace_timestamps)
+            .name(several.then(|| format!("cam{}", i)));
+
+        let opts = if args.raw_stdout {
+            opts.pipeline(false).on_rtp_packet(|rtp| {
+                let raw = rtp.raw();
+                let len = (raw.len() as u16).to_be_bytes();
+
+                let mut stdout = std::io::stdout().lock();
+                let res = stdout.write_all(&len).and_then(|_| stdout.write_all(raw));
+
+                match res {
+                    // Whatever's reading is done
+                    Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => {
+                        std::process::exit(0)
+                    }
+                    Err(err) => tracing::warn!("Can't write to stdout: {}", err),
+                    Ok(()) => {}
+                }
+            })
+        } else if let Some(pcap) = pcap.clone() {
+            opts.on_rtp_packet(move |rtp| {
+                let mut pcap = pcap.lock().unwrap();
+
+                // Keep the streams of each camera on their own ports
+                let stream_id = i * PCAP_STREAMS_PER_URL + rtp.stream_id();
+
+                if let Err(err) = pcap.write(stream_id, rtp.raw(), SystemTime::now()) {
+                    tracing::warn!("Can't write to the pcap file: {}", err);
+                }
+            })
+        } else {
+            opts
+        };
+
+        match metadata.get(i).cloned() {
+            Some(file) => opts.on_metadata(move |message| {
+                let mut file = file.lock().unwrap();
+                let res = file
+                    .write_all(message)
+                    .and_then(|_| file.write_all(b"\n"))
+                    .and_then(|_| file.flush());
+
+                if let Err(err) = res {
+                    tracing::warn!("Can't write to the metadata file: {}", err);
+                }
+            }),
+            None => opts,
+        }
+    };
 
-        if let Some(i) = video_stream_i {
-            session.setup(i, SetupOptions::default()).await?;
+    if args.check {
+        for (encoding_name, missing) in video_stream::missing_encoding_elements()? {
+            if missing.is_empty() {
+                println!("{}: ok", encoding_name);
+            } else {
+                println!("{}: missing {}", encoding_name, missing.join(", "));
+            }
         }
 
-        // Make audio stream
-        // let audio_stream_i = session.streams().iter().position(|s| {
-        //     if s.media == "audio" {
-        //         tracing::info!("Using {} video stream", &s.encoding_name);
-        //         return true;
-        //     }
+        video_stream::check_elements(&opts(0))?;
+        println!("Elements for the given options: ok");
 
-        //     false
-        // });
+        return Ok(());
+    }
 
-        // if let Some(i) = audio_stream_i {
-        //     session.setup(i, SetupOptions::default()).await?;
-        // }
+    if let Some(path) = &args.file {
+        let stop = async {
+            if let Err(err) = shutdown_signal().await {
+                tracing::error!("Can't listen for signals: {}", err);
+                std::future::pending::<()>().await;
+            }
+        };
 
-        // if video_stream_i.is_none() && audio_stream_i.is_none() {
-        //     bail!("Exiting because no video or audio stream was selected; see info log messages above");
-        // }
+        return video_stream::play_file(path, &opts(0), args.loop_file, stop).await;
     }
 
-    let pipeline = gst::Pipeline::new(None);
+    if args.dump_sdp_and_exit {
+        for (i, url) in args.url.iter().enumerate() {
+            let sdp = video_stream::describe(url, &opts(i)).await?;
+            println!("{}", String::from_utf8_lossy(&sdp));
+        }
 
-    let appsrc = {
-        let appsrc = gst::ElementFactory::make("appsrc", Some("rtssrc"))?;
+        return Ok(());
+    }
 
-        {
-            let appsrc = appsrc.clone().dynamic_cast::<gst_app::AppSrc>().unwrap();
+    if args.probe_only {
+        let duration = Duration::from_secs(args.duration.unwrap_or(5));
 
-            appsrc.set_stream_type(gst_app::AppStreamType::Stream);
-            appsrc.set_is_live(true);
-            appsrc.set_format(gst::Format::Time);
-            appsrc.set_do_timestamp(true);
+        for (i, url) in args.url.iter().enumerate() {
+            let opts = opts(i)
+                .reconnect(false)
+                .video_sink("fakesink".to_owned())
+                .mute(true)
+                .count_buffers(true);
 
-            appsrc.set_caps(Some(&gst::Caps::builder("application/x-rtp").build()));
+            probe(url, opts, duration).await?;
         }
 
-        appsrc
-    };
+        return Ok(());
+    }
 
-    let rtpptdemux = {
-        let rtpptdemux = gst::ElementFactory::make("rtpptdemux", Some("rtpptdemux"))?;
+    // One array per URL, a line each
+    if args.print_streams_json {
+        for (i, url) in args.url.iter().enumerate() {
+            println!(
+                "{}",
+                video_stream::describe_streams_json(url, &opts(i)).await?
+            );
+        }
 
-        let pipeline_weak = pipeline.downgrade();
-        rtpptdemux.connect("new-payload-type", false, move |args| {
-            let pt = args[1].get::<u32>().unwrap();
-            let pad = args[2].get::<gst::Pad>().unwrap();
+        return Ok(());
+    }
 
-            pad.set_offset(1000000000);
+    // Fail now rather than in a callback once streams arrive
+    if !args.raw_stdout {
+        video_stream::check_elements(&opts(0))?;
+    }
 
-            let caps = pad.caps().unwrap();
-            tracing::info!("rtpptdemux: new pt={}, caps={:?}", pt, caps);
+    let mut players = Vec::new();
+    for (i, url) in args.url.iter().enumerate() {
+        players.push(Arc::new(RtsPlayer::connect(url.clone(), opts(i)).await?));
+    }
 
-            let s = caps.structure(0).unwrap();
+    let stop_all = {
+        let players = players.clone();
+        move || players.iter().for_each(|player| player.stop())
+    };
 
-            let encoding_name = s.get::<&str>("encoding-name").unwrap();
-            tracing::info!("encoding-name: {:?}", encoding_name);
+    // Stop on Ctrl-C/SIGTERM
+    {
+        let stop_all = stop_all.clone();
 
-            let launch = match encoding_name {
-                "H264" => {
-                    "rtph264depay \
-                    ! h264parse update-timecode=true \
-                    ! vaapidecodebin \
-                    ! videoconvert \
-                    ! autovideosink"
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = shutdown_signal().await {
+                    tracing::error!("Can't listen for signals: {}", err);
+                    break;
                 }
-                _ => "fakesink",
-            };
 
-            if let Some(pipeline) = pipeline_weak.upgrade() {
-                let bin = gst::parse_bin_from_description(launch, true).unwrap();
+                stop_all();
+            }
+        });
+    }
 
-                pipeline.add(&bin).unwrap();
+    if let Some(addr) = args.http_status {
+        let players = players.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = video_stream::serve_status(addr, players).await {
+                tracing::error!("Status server failed: {:#}", err);
+            }
+        });
+    }
 
-                let sink = bin.static_pad("sink").unwrap();
-                pad.link(&sink).unwrap();
+    if let Some(addr) = args.metrics {
+        let players = players.clone();
 
-                bin.set_state(gst::State::Playing).unwrap();
+        tokio::spawn(async move {
+            if let Err(err) = video_stream::serve_metrics(addr, players).await {
+                tracing::error!("Metrics server failed: {:#}", err);
             }
+        });
+    }
+
+    if let Some(duration) = args.duration {
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(duration)).await;
 
-            None
+            tracing::info!("Ran for {}s, stopping", duration);
+            stop_all();
         });
+    }
 
-        rtpptdemux
-    };
+    // Play each camera on its own task; one failing doesn't stop the others
+    let tasks: Vec<_> = players
+        .into_iter()
+        .enumerate()
+        .map(|(i, player)| {
+            let span = tracing::info_span!("camera", i);
+            tokio::spawn(async move { player.run().await }.instrument(span))
+        })
+        .collect();
 
-    {
-        pipeline.add_many(&[&appsrc, &rtpptdemux])?;
-        gst::Element::link_many(&[&appsrc, &rtpptdemux])?;
+    let mut res = Ok(());
+    for (url, task) in args.url.iter().zip(tasks) {
+        if let Err(err) = task.await? {
+            if several {
+                tracing::error!("{} failed: {:#}", url, err);
+            }
+
+            if res.is_ok() {
+                res = Err(err);
+            }
+        }
     }
 
-    pipeline.set_state(gst::State::Playing)?;
+    res
+}
 
-    let appsrc = appsrc.clone().dynamic_cast::<gst_app::AppSrc>().unwrap();
+/// `path` with `-i` before its extension, e.g. `out-1.mp4`.
+fn indexed(path: &Path, i: usize) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(format!("-{}", i));
 
-    let mut session = session.play(retina::client::PlayOptions::default()).await?;
-    let mut bus_stream = pipeline.bus().unwrap().stream();
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
 
-    loop {
-        tokio::select! {
-            pkt = session.next() => {
-                match pkt {
-                    Some(Ok(retina::client::PacketItem::RtpPacket(rtp))) => {
-                        let raw = rtp.raw();
+    path.with_file_name(name)
+}
 
-                        let stream = &session.streams()[rtp.stream_id()];
+/// Plays `url` for `duration`, and prints what got through the branch of each stream set
+/// up.
+async fn probe(url: &url::Url, opts: PlayerOptions, duration: Duration) -> Result<()> {
+    let player = Arc::new(RtsPlayer::connect(url.clone(), opts).await?);
 
-                        let mut buffer = gst::Buffer::with_size(raw.len())?;
+    {
+        let player = player.clone();
 
-                        {
-                            let buffer = buffer.get_mut().unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            player.stop();
+        });
+    }
 
-                            buffer.copy_from_slice(0, raw).unwrap();
-                        }
+    if let Err(err) = player.run().await {
+        println!("{}: failed: {:#}", url, err);
+    }
 
-                        {
-                            let clock_rate = rtp.timestamp().clock_rate().get() as i32;
+    for (pt, branch) in player.branch_stats() {
+        let verdict = if branch.depayed == 0 {
+            "nothing got out of the depayloader"
+        } else if branch.sunk == 0 {
+            "stalled after depaying, e.g. at decoding"
+        } else {
+            "ok"
+        };
 
-                            let caps = gst::Caps::builder("application/x-rtp")
-                                .field("clock-rate", clock_rate)
-                                .field("payload", stream.rtp_payload_type as i32)
-                                .field("media", &stream.media)
-                                .field("encoding-name", &stream.encoding_name.to_uppercase());
+        println!(
+            "{}: pt={} {}: {} buffers depayed, {} into the sink: {}",
+            url, pt, branch.encoding_name, branch.depayed, branch.sunk, verdict
+        );
+    }
 
-                            let caps = if let Some(channels) = stream.channels.map(NonZeroU16::get) {
-                                caps.field("channels", channels as i32)
-                            } else {
-                                caps
-                            };
+    Ok(())
+}
 
-                            let caps = caps.build();
+/// Reads the launch descriptions of the TOML file at `path`, by upper case
+/// encoding-name.
+fn read_config(path: &Path) -> Result<HashMap<String, String>> {
+    let config =
+        std::fs::read_to_string(path).wrap_err_with(|| format!("Can't read {}", path.display()))?;
+    let branches: HashMap<String, String> =
+        toml::from_str(&config).wrap_err_with(|| format!("Can't parse {}", path.display()))?;
 
-                            appsrc.set_caps(Some(&caps));
-                            // application/x-rtp, payload=(int)96, media=(string)video, clock-rate=(int)90000, encoding-name=(string)H264
-                        }
+    Ok(branches
+        .into_iter()
+        .map(|(encoding_name, launch)| (encoding_name.to_uppercase(), launch))
+        .collect())
+}
 
-                        appsrc.push_buffer(buffer)?;
-                    }
-                    Some(Err(err)) => return Err(err.into()),
-                    Some(Ok(retina::client::PacketItem::SenderReport(_sr))) => {}
-                    None => {
-                        let _ = appsrc.end_of_stream()?;
-                        break;
-                    }
-                    Some(Ok(_)) => unreachable!(),
-                }
-            }
-            msg = bus_stream.next() => {
-                if let Some(msg) = msg {
-                    use gst::MessageView;
-
-                    match msg.view() {
-                        MessageView::Eos(_) => break,
-                        MessageView::Error(err) => bail!(err.error()),
-                        _ => {},
-                    }
-                } else {
-                    break
-                }
-            }
+/// Reads the password file at `path`.
+fn read_password(path: &Path) -> Result<String> {
+    let password =
+        std::fs::read_to_string(path).wrap_err_with(|| format!("Can't read {}", path.display()))?;
+
+    Ok(password.trim_end_matches(&['\r', '\n'][..]).to_owned())
+}
+
+/// Parses `PT=ENCODING`.
+fn pt_mapping(s: &str) -> Result<(u8, String), String> {
+    let (pt, encoding_name) = s
+        .split_once('=')
+        .ok_or_else(|| format!("{:?} isn't PT=ENCODING", s))?;
+    let pt = pt
+        .trim()
+        .parse()
+        .map_err(|_| format!("{:?} isn't a payload type", pt))?;
+
+    Ok((pt, encoding_name.trim().to_owned()))
+}
+
+/// Parses `s` as an RTSP URL, with the default port filled in; a URL without a scheme
+/// gets `rtsp://`.
+fn rtsp_url(s: &str) -> Result<url::Url, String> {
+    // Not parsing first, which takes the host of `camera:554` for a scheme
+    let mut url = if s.contains("://") {
+        url::Url::parse(s)
+    } else {
+        url::Url::parse(&format!("rtsp://{}", s))
+    }
+    .map_err(|err| format!("{}: {}", s, err))?;
+
+    let port = match url.scheme() {
+        "rtsp" => 554,
+        "rtsps" => {
+            return Err(
+                "rtsps:// isn't supported: Retina has no RTSP over TLS transport yet".to_owned(),
+            )
         }
+        scheme => {
+            return Err(format!(
+                "{}:// isn't RTSP; the URL should start with rtsp://",
+                scheme
+            ))
+        }
+    };
+
+    if url.host().is_none() {
+        return Err(format!("{} has no host", s));
     }
 
-    pipeline.set_state(gst::State::Null)?;
+    if url.port().is_none() {
+        url.set_port(Some(port)).unwrap();
+    }
 
-    Ok(())
+    if url.path().is_empty() {
+        url.set_path("/");
+    }
+
+    Ok(url)
 }
 
 /// Interpets the `username` and `password` of a [Source].
@@ -265,3 +1040,72 @@ fn creds(
         _ => unreachable!(), // structopt/clap enforce that password requires username.
     }
 }
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigterm = signal(SignalKind::terminate())?;
+
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res,
+            _ = sigterm.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pt_mapping, rtsp_url};
+
+    fn parsed(s: &str) -> String {
+        rtsp_url(s).unwrap().to_string()
+    }
+
+    #[test]
+    fn rtsp_url_without_scheme() {
+        assert_eq!(parsed("camera.local"), "rtsp://camera.local:554/");
+        assert_eq!(
+            parsed("camera.local:8554/stream"),
+            "rtsp://camera.local:8554/stream"
+        );
+        assert_eq!(parsed("localhost:8554"), "rtsp://localhost:8554/");
+    }
+
+    #[test]
+    fn rtsp_url_default_port() {
+        assert_eq!(parsed("rtsp://camera.local"), "rtsp://camera.local:554/");
+        assert_eq!(
+            parsed("rtsp://camera.local:8554/stream"),
+            "rtsp://camera.local:8554/stream"
+        );
+    }
+
+    #[test]
+    fn rtsp_url_rejects_other_schemes() {
+        assert!(rtsp_url("http://camera.local/").is_err());
+        assert!(rtsp_url("ftp://camera.local/").is_err());
+        assert!(rtsp_url("rtsps://camera.local/stream").is_err());
+    }
+
+    #[test]
+    fn pt_mapping_parses() {
+        assert_eq!(pt_mapping("96=H264"), Ok((96, "H264".to_owned())));
+        assert_eq!(
+            pt_mapping(" 97 = MPEG4-GENERIC "),
+            Ok((97, "MPEG4-GENERIC".to_owned()))
+        );
+    }
+
+    #[test]
+    fn pt_mapping_rejects() {
+        assert!(pt_mapping("H264").is_err());
+        assert!(pt_mapping("x=H264").is_err());
+        assert!(pt_mapping("256=H264").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Writes RTP packets to a pcap file, in made up IPv4/UDP headers so Wireshark's RTP
+//! dissector can take it from there.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `LINKTYPE_RAW`: packets start with the IP header.
+const LINKTYPE_RAW: u32 = 101;
+
+/// UDP port of stream 0, as sender and receiver; the others follow, two ports apart.
+const BASE_PORT: u16 = 5004;
+
+/// A pcap file with one made up UDP port pair per stream.
+pub struct PcapWriter<W: Write> {
+    out: W,
+}
+
+impl PcapWriter<BufWriter<File>> {
+    /// Creates the file at `path`, replacing any.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the pcap header to `out`.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(&0xa1b2_c3d4u32.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&4u16.to_le_bytes())?;
+        out.write_all(&0i32.to_le_bytes())?; // thiszone
+        out.write_all(&0u32.to_le_bytes())?; // sigfigs
+        out.write_all(&65535u32.to_le_bytes())?; // snaplen
+        out.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+
+        Ok(PcapWriter { out })
+    }
+
+    /// Writes the RTP packet `rtp` of `stream_id`, received at `time`.
+    pub fn write(&mut self, stream_id: usize, rtp: &[u8], time: SystemTime) -> io::Result<()> {
+        let port = BASE_PORT.wrapping_add((stream_id as u16).wrapping_mul(2));
+
+        let udp_len = 8 + rtp.len();
+        let ip_len = 20 + udp_len;
+
+        let mut ip: [u8; 20] = [
+            0x45, 0, // version 4, 20 byte header
+            0, 0, // total length
+            0, 0, 0x40, 0, // no fragments
+            64, 17, // TTL, UDP
+            0, 0, // checksum
+            10, 0, 0, 1, // "camera"
+            10, 0, 0, 2, // "us"
+        ];
+        ip[2..4].copy_from_slice(&(ip_len as u16).to_be_bytes());
+        let checksum = ip_checksum(&ip);
+        ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        let mut udp = [0u8; 8];
+        udp[0..2].copy_from_slice(&port.to_be_bytes());
+        udp[2..4].copy_from_slice(&port.to_be_bytes());
+        udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+        // A zero checksum means none
+
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        self.out
+            .write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+        self.out
+            .write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+        self.out.write_all(&(ip_len as u32).to_le_bytes())?;
+        self.out.write_all(&(ip_len as u32).to_le_bytes())?;
+
+        self.out.write_all(&ip)?;
+        self.out.write_all(&udp)?;
+        self.out.write_all(rtp)
+    }
+}
+
+/// The one's complement sum IPv4 headers are checked with.
+fn ip_checksum(header: &[u8]) -> u16 {
+    let sum = header
+        .chunks(2)
+        .map(|word| u32::from(u16::from_be_bytes([word[0], word[1]])))
+        .sum::<u32>();
+
+    let sum = (sum & 0xffff) + (sum >> 16);
+    let sum = (sum & 0xffff) + (sum >> 16);
+
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ip_checksum;
+
+    #[test]
+    fn checksum() {
+        let mut header: [u8; 20] = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(ip_checksum(&header), 0xb861);
+
+        // And one with it sums to all ones
+        header[10..12].copy_from_slice(&0xb861u16.to_be_bytes());
+        assert_eq!(ip_checksum(&header), 0);
+    }
+}
@@ -0,0 +1,235 @@
+//! RTP packet counters per stream, and buffer counters per branch.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use gst::prelude::*;
+
+/// What's been received on one stream.
+#[derive(Debug, Clone, Default)]
+pub struct StreamStats {
+    /// e.g. `video` or `audio`.
+    pub media: String,
+    pub packets: u64,
+    pub bytes: u64,
+    /// Estimated from gaps in the RTP sequence numbers.
+    pub lost: u64,
+    /// When the latest packet arrived.
+    pub last_packet: Option<Instant>,
+    first_packet: Option<Instant>,
+    last_seq: Option<u16>,
+    /// The sequence number after a jump, which if it comes next means the sender
+    /// restarted its sequence, as RFC 3550 A.1 has it.
+    bad_seq: Option<u16>,
+}
+
+impl StreamStats {
+    /// Average bitrate since the first packet.
+    pub fn kbps(&self) -> f64 {
+        let secs = match (self.first_packet, self.last_packet) {
+            (Some(first), Some(last)) => (last - first).as_secs_f64(),
+            _ => 0.0,
+        };
+
+        if secs > 0.0 {
+            self.bytes as f64 * 8.0 / secs / 1000.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Gaps in the sequence numbers up to this are loss; RFC 3550 A.1's.
+const MAX_DROPOUT: u16 = 3000;
+
+/// Sequence numbers up to this behind the last one are reordered; RFC 3550 A.1's.
+const MAX_MISORDER: u16 = 100;
+
+/// Counters of all streams, shared between the packet loop and whoever reports them.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Stats(Arc<Mutex<BTreeMap<usize, StreamStats>>>);
+
+impl Stats {
+    /// Counts the RTP packet `raw` received on `stream_id`, a `media` stream.
+    pub(crate) fn record(&self, stream_id: usize, media: &str, raw: &[u8]) {
+        let mut streams = self.0.lock().unwrap();
+        let stats = streams.entry(stream_id).or_default();
+
+        if stats.media.is_empty() {
+            stats.media = media.to_owned();
+        }
+
+        stats.packets += 1;
+        stats.bytes += raw.len() as u64;
+
+        let now = Instant::now();
+        stats.first_packet.get_or_insert(now);
+        stats.last_packet = Some(now);
+
+        if raw.len() >= 4 {
+            let seq = u16::from_be_bytes([raw[2], raw[3]]);
+
+            match stats.last_seq.map(|last| seq.wrapping_sub(last)) {
+                // Duplicated
+                Some(0) => {}
+                Some(gap) if gap < MAX_DROPOUT => {
+                    stats.lost += u64::from(gap - 1);
+                    stats.last_seq = Some(seq);
+                    stats.bad_seq = None;
+                }
+                // Reordered
+                Some(gap) if gap > u16::MAX - MAX_MISORDER => {}
+                // Too far to be loss, so a restarted sequence once the next one follows
+                Some(_) if stats.bad_seq == Some(seq) => {
+                    stats.last_seq = Some(seq);
+                    stats.bad_seq = None;
+                }
+                Some(_) => stats.bad_seq = Some(seq.wrapping_add(1)),
+                None => stats.last_seq = Some(seq),
+            }
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> BTreeMap<usize, StreamStats> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Buffers that went through the branch of one payload type.
+#[derive(Debug, Clone, Default)]
+pub struct BranchStats {
+    /// e.g. `H264`.
+    pub encoding_name: String,
+    /// Out of the depayloader.
+    pub depayed: u64,
+    /// Into the sinks, so e.g. decoded.
+    pub sunk: u64,
+}
+
+/// Counters of all branches, by payload type.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BranchCounters(Arc<Mutex<BTreeMap<u32, BranchStats>>>);
+
+impl BranchCounters {
+    /// Counts the buffers out of the depayloader of `bin`, of `pt`, and into its sinks.
+    pub(crate) fn count(&self, bin: &gst::Bin, pt: u32, encoding_name: &str) {
+        self.0.lock().unwrap().insert(
+            pt,
+            BranchStats {
+                encoding_name: encoding_name.to_owned(),
+                ..Default::default()
+            },
+        );
+
+        let is_depayloader = |element: &gst::Element| {
+            element
+                .factory()
+                .and_then(|factory| factory.metadata("klass").map(|k| k.contains("Depayloader")))
+                .unwrap_or(false)
+        };
+
+        for element in bin.iterate_elements().into_iter().flatten() {
+            let pad = if is_depayloader(&element) {
+                element.static_pad("src")
+            } else if element.element_flags().contains(gst::ElementFlags::SINK) {
+                element.static_pad("sink")
+            } else {
+                None
+            };
+
+            if let Some(pad) = pad {
+                let counters = self.clone();
+                let depayed = pad.direction() == gst::PadDirection::Src;
+
+                pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+                    if let Some(stats) = counters.0.lock().unwrap().get_mut(&pt) {
+                        if depayed {
+                            stats.depayed += 1;
+                        } else {
+                            stats.sunk += 1;
+                        }
+                    }
+
+                    gst::PadProbeReturn::Ok
+                });
+            }
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> BTreeMap<u32, BranchStats> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Logs `stats` every `interval`, forever.
+pub(crate) async fn log_every(stats: Stats, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    let mut prev = BTreeMap::new();
+
+    // The first tick completes immediately
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let now = stats.snapshot();
+
+        for (stream_id, s) in &now {
+            let prev_bytes = prev.get(stream_id).map_or(0, |p: &StreamStats| p.bytes);
+            let kbps = (s.bytes - prev_bytes) as f64 * 8.0 / interval.as_secs_f64() / 1000.0;
+
+            tracing::info!(
+                "Stream {}: {} packets, {} bytes, {:.0} kbit/s, ~{} lost",
+                stream_id,
+                s.packets,
+                s.bytes,
+                kbps,
+                s.lost
+            );
+        }
+
+        prev = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stats;
+
+    /// Records packets of these sequence numbers on stream 0, returning how many were
+    /// lost.
+    fn lost(seqs: &[u16]) -> u64 {
+        let stats = Stats::default();
+        for &seq in seqs {
+            let mut raw = [0x80, 96, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+            raw[2..4].copy_from_slice(&seq.to_be_bytes());
+            stats.record(0, "video", &raw);
+        }
+
+        stats.snapshot()[&0].lost
+    }
+
+    #[test]
+    fn counts_gaps() {
+        assert_eq!(lost(&[1, 2, 3]), 0);
+        assert_eq!(lost(&[1, 2, 5, 6]), 2);
+    }
+
+    #[test]
+    fn ignores_duplicates_and_reordering() {
+        assert_eq!(lost(&[1, 2, 2, 3]), 0);
+        assert_eq!(lost(&[1, 3, 2, 4]), 1);
+    }
+
+    #[test]
+    fn counts_across_the_wrap() {
+        assert_eq!(lost(&[65534, 65535, 0, 1]), 0);
+        assert_eq!(lost(&[65534, 1]), 2);
+    }
+
+    #[test]
+    fn resyncs_after_a_sequence_reset() {
+        assert_eq!(lost(&[40000, 40001, 7, 8, 9, 11]), 1);
+    }
+}
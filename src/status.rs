@@ -0,0 +1,237 @@
+//! A tiny HTTP server for liveness probes, stats and Prometheus metrics.
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use color_eyre::Result;
+
+use crate::RtsPlayer;
+
+/// How recently a player must have received a packet to be healthy.
+const MAX_PACKET_AGE: Duration = Duration::from_secs(5);
+
+/// An HTTP response: status line, content type and body.
+type Response = (&'static str, &'static str, String);
+
+/// Serves `/healthz` and `/stats` of `players` on `addr`, forever.
+///
+/// `/healthz` is 200 when every pipeline is playing and got a packet in the last few
+/// seconds, 503 otherwise. `/stats` is JSON with the per-stream counters of each player.
+pub async fn serve_status(addr: SocketAddr, players: Vec<Arc<RtsPlayer>>) -> Result<()> {
+    serve(addr, players, status).await
+}
+
+/// Serves `/metrics` of `players` in the Prometheus text format on `addr`, forever.
+pub async fn serve_metrics(addr: SocketAddr, players: Vec<Arc<RtsPlayer>>) -> Result<()> {
+    serve(addr, players, metrics).await
+}
+
+/// Answers requests on `addr` with `respond(path, players)`.
+async fn serve(
+    addr: SocketAddr,
+    players: Vec<Arc<RtsPlayer>>,
+    respond: fn(&str, &[Arc<RtsPlayer>]) -> Response,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("Serving on http://{}", listener.local_addr()?);
+
+    let players = Arc::new(players);
+
+    loop {
+        let (conn, _) = listener.accept().await?;
+        let players = players.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = handle(conn, &players, respond).await {
+                tracing::debug!("HTTP request failed: {}", err);
+            }
+        });
+    }
+}
+
+/// Answers the one request on `conn`.
+async fn handle(
+    conn: TcpStream,
+    players: &[Arc<RtsPlayer>],
+    respond: fn(&str, &[Arc<RtsPlayer>]) -> Response,
+) -> Result<()> {
+    let mut conn = BufReader::new(conn);
+
+    // e.g. `GET /healthz HTTP/1.1`
+    let mut request = String::new();
+    conn.read_line(&mut request).await?;
+
+    // Skip the headers
+    loop {
+        let mut line = String::new();
+        if conn.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => respond(path, players),
+        _ => not_found(),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+
+    conn.get_mut().write_all(response.as_bytes()).await?;
+    conn.get_mut().shutdown().await?;
+
+    Ok(())
+}
+
+/// The `/healthz` and `/stats` endpoints.
+fn status(path: &str, players: &[Arc<RtsPlayer>]) -> Response {
+    match path {
+        "/healthz" if players.iter().all(|p| healthy(p)) => {
+            ("200 OK", "text/plain", "ok\n".to_owned())
+        }
+        "/healthz" => (
+            "503 Service Unavailable",
+            "text/plain",
+            "unhealthy\n".to_owned(),
+        ),
+        "/stats" => ("200 OK", "application/json", stats_json(players)),
+        _ => not_found(),
+    }
+}
+
+/// The `/metrics` endpoint.
+fn metrics(path: &str, players: &[Arc<RtsPlayer>]) -> Response {
+    match path {
+        "/metrics" => ("200 OK", "text/plain; version=0.0.4", metrics_text(players)),
+        _ => not_found(),
+    }
+}
+
+fn not_found() -> Response {
+    ("404 Not Found", "text/plain", "not found\n".to_owned())
+}
+
+/// Whether `player`'s pipeline, if it has one, is playing and it got a packet recently.
+fn healthy(player: &RtsPlayer) -> bool {
+    (!player.has_pipeline() || player.state() == gst::State::Playing)
+        && player
+            .stats()
+            .values()
+            .filter_map(|s| s.last_packet)
+            .any(|last| last.elapsed() < MAX_PACKET_AGE)
+}
+
+/// e.g. `[{"state":"Playing","streams":{"0":{"packets":10,"bytes":12000,"lost":0,"kbps":96.0}}}]`
+fn stats_json(players: &[Arc<RtsPlayer>]) -> String {
+    let mut json = String::from("[");
+
+    for (i, player) in players.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+
+        let _ = write!(json, "{{\"state\":\"{:?}\",\"streams\":{{", player.state());
+
+        for (j, (stream_id, s)) in player.stats().iter().enumerate() {
+            if j > 0 {
+                json.push(',');
+            }
+
+            let _ = write!(
+                json,
+                "\"{}\":{{\"packets\":{},\"bytes\":{},\"lost\":{},\"kbps\":{:.1}}}",
+                stream_id,
+                s.packets,
+                s.bytes,
+                s.lost,
+                s.kbps()
+            );
+        }
+
+        json.push_str("}}");
+    }
+
+    json.push_str("]\n");
+    json
+}
+
+/// The metrics of `players`, labelled by camera index, and stream index and media type.
+fn metrics_text(players: &[Arc<RtsPlayer>]) -> String {
+    let mut text = String::new();
+    let stats: Vec<_> = players.iter().map(|p| p.stats()).collect();
+
+    let counters: [(&str, &str, fn(&crate::StreamStats) -> u64); 2] = [
+        ("rtsplay_rtp_packets_total", "RTP packets received.", |s| {
+            s.packets
+        }),
+        ("rtsplay_rtp_bytes_total", "RTP bytes received.", |s| {
+            s.bytes
+        }),
+    ];
+
+    for (name, help, value) in counters {
+        let _ = writeln!(text, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+
+        for (camera, streams) in stats.iter().enumerate() {
+            for (stream_id, s) in streams {
+                let _ = writeln!(
+                    text,
+                    "{}{{camera=\"{}\",stream=\"{}\",media=\"{}\"}} {}",
+                    name,
+                    camera,
+                    stream_id,
+                    s.media,
+                    value(s)
+                );
+            }
+        }
+    }
+
+    let _ = writeln!(
+        text,
+        "# HELP rtsplay_reconnects_total Sessions reconnected.\n\
+        # TYPE rtsplay_reconnects_total counter"
+    );
+    for (camera, player) in players.iter().enumerate() {
+        let _ = writeln!(
+            text,
+            "rtsplay_reconnects_total{{camera=\"{}\"}} {}",
+            camera,
+            player.reconnects()
+        );
+    }
+
+    let _ = writeln!(
+        text,
+        "# HELP rtsplay_pipeline_state Pipeline state: 1 null, 2 ready, 3 paused, 4 playing.\n\
+        # TYPE rtsplay_pipeline_state gauge"
+    );
+    for (camera, player) in players.iter().enumerate() {
+        let state = match player.state() {
+            gst::State::Null => 1,
+            gst::State::Ready => 2,
+            gst::State::Paused => 3,
+            gst::State::Playing => 4,
+            _ => 0,
+        };
+
+        let _ = writeln!(
+            text,
+            "rtsplay_pipeline_state{{camera=\"{}\"}} {}",
+            camera, state
+        );
+    }
+
+    text
+}
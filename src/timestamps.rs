@@ -0,0 +1,106 @@
+//! Buffer timestamps from RTP timestamps and RTCP sender reports.
+
+use std::collections::HashMap;
+
+use gst::prelude::*;
+
+/// Maps RTP timestamps to the sender's NTP wallclock, so all streams share one timeline,
+/// and that onto the pipeline's running time. Until a stream's first sender report, its
+/// RTP timestamps count from the running time its first packet arrived at.
+///
+/// On a wallclock [crate::PipelineClock], the NTP time maps straight onto the clock,
+/// so pipelines sharing a base time play cameras with synced clocks in sync.
+#[derive(Debug, Default)]
+pub(crate) struct Timestamps {
+    /// `(RTP timestamp, running time in ns)` of each stream's first packet.
+    firsts: HashMap<usize, (i64, i128)>,
+    /// `(RTP timestamp, NTP time in ns)` of each stream's latest sender report.
+    srs: HashMap<usize, (i64, i128)>,
+    /// NTP time in ns of the first timestamped buffer, and its running time.
+    base: Option<(i128, i128)>,
+    /// NTP time in ns of the pipeline clock's zero, if it's a wallclock.
+    clock_epoch: Option<i128>,
+}
+
+impl Timestamps {
+    pub(crate) fn new(clock_epoch: Option<i128>) -> Self {
+        Timestamps {
+            clock_epoch,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn sender_report(
+        &mut self,
+        stream_id: usize,
+        rtp_timestamp: retina::Timestamp,
+        ntp_timestamp: retina::NtpTimestamp,
+    ) {
+        self.srs.insert(
+            stream_id,
+            (rtp_timestamp.timestamp(), ntp_nanos(ntp_timestamp)),
+        );
+    }
+
+    /// The PTS of a packet of `stream_id` with RTP timestamp `ts`, once `element` has a
+    /// clock.
+    pub(crate) fn pts(
+        &mut self,
+        stream_id: usize,
+        ts: retina::Timestamp,
+        element: &impl IsA<gst::Element>,
+    ) -> Option<gst::ClockTime> {
+        let clock_rate = i128::from(ts.clock_rate().get());
+
+        let pts = match self.srs.get(&stream_id).copied() {
+            Some((sr_rtp, sr_ntp)) => {
+                let ntp = sr_ntp + i128::from(ts.timestamp() - sr_rtp) * 1_000_000_000 / clock_rate;
+
+                if let Some(epoch) = self.clock_epoch {
+                    let base_time = i128::from(element.base_time()?.nseconds());
+                    return u64::try_from(ntp - epoch - base_time)
+                        .ok()
+                        .map(gst::ClockTime::from_nseconds);
+                }
+
+                let (base_ntp, base_running_time) = match self.base {
+                    Some(base) => base,
+                    None => *self.base.insert((ntp, running_time(element)?)),
+                };
+
+                base_running_time + (ntp - base_ntp)
+            }
+            None => {
+                let (first_rtp, first_running_time) = match self.firsts.get(&stream_id).copied() {
+                    Some(first) => first,
+                    None => {
+                        let first = (ts.timestamp(), running_time(element)?);
+                        self.firsts.insert(stream_id, first);
+                        first
+                    }
+                };
+
+                first_running_time
+                    + i128::from(ts.timestamp() - first_rtp) * 1_000_000_000 / clock_rate
+            }
+        };
+
+        u64::try_from(pts).ok().map(gst::ClockTime::from_nseconds)
+    }
+}
+
+/// Running time of `element` now, in ns.
+fn running_time(element: &impl IsA<gst::Element>) -> Option<i128> {
+    let now = element.clock()?.time()?;
+    let running_time = now.checked_sub(element.base_time()?)?;
+
+    Some(i128::from(running_time.nseconds()))
+}
+
+/// Converts 32.32 fixed point seconds since 1900 to ns.
+fn ntp_nanos(ntp: retina::NtpTimestamp) -> i128 {
+    let secs = i128::from(ntp.0 >> 32);
+    let frac = i128::from(ntp.0 & 0xffff_ffff);
+
+    secs * 1_000_000_000 + ((frac * 1_000_000_000) >> 32)
+}
@@ -0,0 +1,173 @@
+//! Plays a local `gst-rtsp-server` test stream headless, as a regression baseline for
+//! the whole connect, play and stop path.
+
+use std::io::Read;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Once};
+use std::time::Duration;
+
+use gst::glib;
+use gst_rtsp_server::prelude::*;
+
+use video_stream::{PlayerOptions, RtsPlayer, TransportKind};
+
+const PACKETS: u64 = 100;
+
+/// An H.264 test stream.
+const H264: &str = "( videotestsrc is-live=true ! video/x-raw,width=320,height=240 \
+    ! x264enc tune=zerolatency key-int-max=30 ! rtph264pay name=pay0 pt=96 )";
+
+/// A mono G.711 A-law test stream, whose SDP has no channels.
+const PCMA: &str = "( audiotestsrc is-live=true ! audio/x-raw,rate=8000,channels=1 \
+    ! alawenc ! rtppcmapay name=pay0 pt=8 )";
+
+/// Serves the `launch` stream at `/test` on a free port, returning its URL; `None`
+/// without its `encoder`.
+fn serve(launch: &str, encoder: &str) -> Option<url::Url> {
+    static MAIN_LOOP: Once = Once::new();
+
+    gst::init().unwrap();
+
+    if gst::ElementFactory::find(encoder).is_none() {
+        eprintln!("Skipping: {} isn't installed", encoder);
+        return None;
+    }
+
+    let server = gst_rtsp_server::RTSPServer::new();
+    server.set_service("0");
+
+    let factory = gst_rtsp_server::RTSPMediaFactory::new();
+    factory.set_launch(launch);
+    factory.set_shared(true);
+    server
+        .mount_points()
+        .unwrap()
+        .add_factory("/test", &factory);
+
+    server.attach(None).unwrap();
+    let port = server.bound_port();
+
+    // Every server is on the default main context
+    MAIN_LOOP.call_once(|| {
+        std::thread::spawn(|| glib::MainLoop::new(None, false).run());
+    });
+
+    Some(format!("rtsp://127.0.0.1:{}/test", port).parse().unwrap())
+}
+
+/// Headless options that stop after [PACKETS].
+fn options() -> PlayerOptions {
+    PlayerOptions::default()
+        .transport(Some(TransportKind::Tcp))
+        .headless(true)
+        .audio(false)
+        .connect_timeout(Some(Duration::from_secs(10)))
+        .read_timeout(Some(Duration::from_secs(10)))
+        .max_packets(Some(PACKETS))
+}
+
+/// Plays `url` until it stops, checking the pipeline got to PLAYING and the packets
+/// arrived.
+async fn play(url: url::Url, opts: PlayerOptions) {
+    let player = Arc::new(RtsPlayer::connect(url, opts).await.unwrap());
+
+    // The state is `Null` again once run returns, so watch it while playing
+    let played = Arc::new(AtomicBool::new(false));
+    let watch = tokio::spawn({
+        let player = player.clone();
+        let played = played.clone();
+        async move {
+            loop {
+                if player.state() == gst::State::Playing {
+                    played.store(true, Ordering::Relaxed);
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    });
+
+    let run = tokio::time::timeout(Duration::from_secs(30), player.run()).await;
+    watch.abort();
+
+    run.expect("still playing after 30s").unwrap();
+
+    assert!(
+        played.load(Ordering::Relaxed),
+        "pipeline never got to PLAYING"
+    );
+
+    let packets: u64 = player.stats().values().map(|stats| stats.packets).sum();
+    assert!(packets >= PACKETS, "only {} packets", packets);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn plays_headless() {
+    if let Some(url) = serve(H264, "x264enc") {
+        play(url, options()).await;
+    }
+}
+
+/// The same seed impairs the same packets, so a failure here reproduces.
+#[tokio::test(flavor = "multi_thread")]
+async fn plays_through_loss_and_reordering() {
+    if let Some(url) = serve(H264, "x264enc") {
+        let opts = options()
+            .jitter_latency(Some(Duration::from_millis(200)))
+            .simulate_loss(0.05)
+            .simulate_reorder(0.05)
+            .seed(1);
+
+        play(url, opts).await;
+    }
+}
+
+/// The channels default to mono, as alawdec needs them.
+#[tokio::test(flavor = "multi_thread")]
+async fn plays_g711_without_channels() {
+    if let Some(url) = serve(PCMA, "alawenc") {
+        play(url, options().audio(true).mute(true)).await;
+    }
+}
+
+/// A sink whose peer hangs up on it gets restarted, and the session plays on.
+#[tokio::test(flavor = "multi_thread")]
+async fn recovers_from_a_sink_error() {
+    let url = match serve(H264, "x264enc") {
+        Some(url) => url,
+        None => return,
+    };
+
+    // Hangs up the first connection, and drains the others
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let connections = Arc::new(AtomicUsize::new(0));
+    std::thread::spawn({
+        let connections = connections.clone();
+        move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                if connections.fetch_add(1, Ordering::Relaxed) == 0 {
+                    let _ = stream.read(&mut [0; 1024]);
+                } else {
+                    std::thread::spawn(move || std::io::copy(&mut stream, &mut std::io::sink()));
+                }
+            }
+        }
+    });
+
+    let branch = format!(
+        "rtph264depay ! h264parse ! tcpclientsink host=127.0.0.1 port={}",
+        port
+    );
+    let opts = options()
+        .branches([("H264".to_owned(), branch)].into_iter().collect())
+        .recover_on_error(true);
+
+    play(url, opts).await;
+
+    assert!(
+        connections.load(Ordering::Relaxed) >= 2,
+        "the sink was never restarted"
+    );
+}